@@ -0,0 +1,42 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use andaluh::*;
+
+const TEXT: &str = "Todo Xenomorfo dice: [haber], que el Éxito y el éxtasis asfixian, si no eres un xilófono Chungo.
+Lleva un Guijarrito el ABuelo, ¡Qué Bueno! ¡para la VERGÜENZA!
+Alrededor de la Alpaca había un ALfabeto ALTIVO de valkirias malnacidas.
+En la Zaragoza y el Japón asexual se Sabía SÉriamente sILBAR con el COxis.
+Venid todos a correr en anorak de visón a Cádiz con actitud y maldad, para escuchar el tríceps de Albéniz.
+";
+
+// The pipeline as epa ran it before, every rule over the whole text
+fn rules_one_by_one(input: &str) -> Result<String, Error> {
+    let rules = [h_rule, x_rule, ch_rule, gj_rule, v_rule, ll_rule, l_rule, psico_rule,
+                 vaf_rule, word_ending_rule, digraph_rule, exception_rule, word_interaction_rule];
+    let mut output = input.to_string();
+    for r in rules.iter() {
+        output = r(&output)?;
+    }
+
+    Ok(output)
+}
+
+fn bench_epa(c: &mut Criterion) {
+    let mut group = c.benchmark_group("epa");
+    for lines in [1, 10, 100].iter() {
+        let input = TEXT.repeat(*lines);
+        assert_eq!(epa(&input).unwrap(), rules_one_by_one(&input).unwrap());
+
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("by_words", lines), &input, |b, input| {
+            b.iter(|| epa(input).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("rules_one_by_one", lines), &input, |b, input| {
+            b.iter(|| rules_one_by_one(input).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_epa);
+criterion_main!(benches);
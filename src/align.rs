@@ -0,0 +1,173 @@
+// Alignment of the output with the input, composing what every step of a
+// trace rewrote.
+
+use std::ops::Range;
+
+use crate::{Match, Step};
+
+/// A piece of the output and the piece of the input it comes from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alignment {
+    /// Byte range in the input
+    pub source: Range<usize>,
+    /// Byte range in the output, empty for input the rules dropped
+    pub output: Range<usize>,
+    /// Whether a rule rewrote it, unchanged pieces are the same text
+    pub changed: bool,
+}
+
+// Pieces of a step, tiling both its input and its output
+fn step_alignment(step: &Step) -> Vec<Alignment> {
+    let mut pieces = vec![];
+    let mut source = 0;
+    let mut output = 0;
+
+    let unchanged = |pieces: &mut Vec<Alignment>, source: usize, output: usize, len: usize| {
+        if len > 0 {
+            pieces.push(Alignment { source: source..source + len, output: output..output + len, changed: false });
+        }
+    };
+
+    for Match { span, output: out, .. } in step.matches.iter() {
+        let gap = span.start - source;
+        unchanged(&mut pieces, source, output, gap);
+        output += gap;
+
+        pieces.push(Alignment { source: span.clone(), output: output..output + out.len(), changed: true });
+        source = span.end;
+        output += out.len();
+    }
+    unchanged(&mut pieces, source, output, step.input.len() - source);
+
+    pieces
+}
+
+// Whether the piece of a, in the middle text, belongs to the piece of b
+fn overlaps(a: &Range<usize>, b: &Range<usize>, end: usize) -> bool {
+    match a.is_empty() {
+        // Dropped text goes with the piece after it, or the last one
+        true => b.start <= a.start && (a.start < b.end || (a.start == b.end && b.end == end)),
+        false => a.start < b.end && b.start < a.end,
+    }
+}
+
+// Source of the part of the middle text in range, within the piece of a
+fn source_of(a: &Alignment, range: &Range<usize>) -> Range<usize> {
+    match a.changed {
+        true => a.source.clone(),
+        false => {
+            let start = range.start.max(a.output.start) - a.output.start + a.source.start;
+            let end = range.end.min(a.output.end) - a.output.start + a.source.start;
+            start..end
+        },
+    }
+}
+
+// Composes a, from the input to a middle text, with b, from it to the output
+fn compose(a: &[Alignment], b: &[Alignment], middle: usize) -> Vec<Alignment> {
+    let mut pieces: Vec<Alignment> = vec![];
+    let mut first = 0;
+
+    for piece in b {
+        while first < a.len() && a[first].output.end < piece.source.start {
+            first += 1;
+        }
+        let within = a[first..].iter()
+            .take_while(|p| p.output.start <= piece.source.end)
+            .filter(|p| overlaps(&p.output, &piece.source, middle));
+
+        match piece.changed {
+            true => {
+                let source = within.map(|p| source_of(p, &piece.source))
+                    .reduce(|s, r| s.start.min(r.start)..s.end.max(r.end));
+                let source = source.unwrap_or_else(|| {
+                    // Text a rule added, it comes from where it was added
+                    let at = a.iter().find(|p| p.output.end >= piece.source.start)
+                        .map_or(0, |p| source_of(p, &piece.source).start);
+                    at..at
+                });
+                pieces.push(Alignment { source, output: piece.output.clone(), changed: true });
+            },
+            false => {
+                for p in within {
+                    let start = p.output.start.max(piece.source.start);
+                    let end = p.output.end.min(piece.source.end);
+                    let output = start - piece.source.start + piece.output.start..
+                        end - piece.source.start + piece.output.start;
+                    pieces.push(Alignment {
+                        source: source_of(p, &(start..end)),
+                        output,
+                        changed: p.changed,
+                    });
+                }
+            },
+        }
+    }
+
+    merge(pieces)
+}
+
+// Joins pieces split by an unchanged piece of a later step
+fn merge(pieces: Vec<Alignment>) -> Vec<Alignment> {
+    let mut merged: Vec<Alignment> = vec![];
+
+    for piece in pieces {
+        if let Some(last) = merged.last_mut() {
+            let same = match (last.changed, piece.changed) {
+                (true, true) => piece.source.start < last.source.end || last.source == piece.source,
+                (false, false) => last.source.end == piece.source.start,
+                _ => false,
+            };
+            if same && last.output.end == piece.output.start {
+                last.source = last.source.start..piece.source.end.max(last.source.end);
+                last.output = last.output.start..piece.output.end;
+                continue;
+            }
+        }
+        merged.push(piece);
+    }
+
+    merged
+}
+
+/// Alignment of the output of the last step with the input of the first one
+pub(crate) fn align(input: &str, steps: &[Step]) -> Vec<Alignment> {
+    let mut pieces = match input.len() {
+        0 => vec![],
+        n => vec![Alignment { source: 0..n, output: 0..n, changed: false }],
+    };
+
+    for step in steps {
+        pieces = compose(&pieces, &step_alignment(step), step.input.len());
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{epa_aligned, EpaOptions, Vaf};
+
+    #[test]
+    fn test_align() {
+        let input = "Hola de Madrid, el transcurso";
+        let options = EpaOptions { vaf: Vaf::S, ..Default::default() };
+        let (output, alignment) = epa_aligned(input, &options).expect("Wrong parser");
+        assert_eq!(output, "Ola de Madrîh, er trâccurso");
+
+        let pieces: Vec<(&str, &str, bool)> = alignment.iter()
+            .map(|a| (&input[a.source.clone()], &output[a.output.clone()], a.changed))
+            .collect();
+        assert_eq!(pieces, vec![
+            ("Ho", "O", true),
+            ("la de ", "la de ", false),
+            ("Madrid", "Madrîh", true),
+            (", ", ", ", false),
+            // Both rules rewrote the t, so the pieces join
+            ("el transc", "er trâcc", true),
+            ("ur", "ur", false),
+            // Written with ç by vaf_rule and as s for the VAF at the end
+            ("so", "so", true),
+        ]);
+    }
+}
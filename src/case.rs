@@ -0,0 +1,77 @@
+// Case pattern of a source text, to write a replacement of any length with
+// the same case.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Case {
+    Lower,
+    Upper,
+    Title,
+    // Uppercase flag of every grapheme
+    Mixed(Vec<bool>),
+}
+
+impl Case {
+    pub(crate) fn of(source: &str) -> Case {
+        let flags: Vec<Option<bool>> = source.graphemes(true)
+            .map(|g| {
+                let c = g.chars().next().unwrap_or('x');
+                match (c.is_uppercase(), c.is_lowercase()) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                }
+            }).collect();
+
+        let mut cased = flags.iter().flatten();
+        let first = cased.next();
+        let rest_upper: Vec<bool> = cased.cloned().collect();
+
+        match first {
+            None | Some(false) if rest_upper.iter().all(|u| !u) => Case::Lower,
+            Some(true) if rest_upper.is_empty() => Case::Title,
+            Some(true) if rest_upper.iter().all(|u| *u) => Case::Upper,
+            Some(true) if rest_upper.iter().all(|u| !u) => Case::Title,
+            _ => Case::Mixed(flags.iter().map(|f| f.unwrap_or(false)).collect()),
+        }
+    }
+
+    pub(crate) fn apply(&self, target: &str) -> String {
+        let upper = |i: usize| match self {
+            Case::Lower => false,
+            Case::Upper => true,
+            Case::Title => i == 0,
+            // Graphemes past the source keep the case of its last one
+            Case::Mixed(flags) => *flags.get(i).or_else(|| flags.last()).unwrap_or(&false),
+        };
+
+        target.graphemes(true).enumerate()
+            .map(|(i, g)| match upper(i) {
+                true => g.to_uppercase(),
+                false => g.to_lowercase(),
+            }).collect::<String>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case() {
+        let test_cases = vec![
+            ("uir", "huîh", Case::Lower, "huîh"),
+            ("STOP", "êttôh", Case::Upper, "ÊTTÔH"),
+            ("Uir", "huîh", Case::Title, "Huîh"),
+            ("H", "güe", Case::Title, "Güe"),
+            ("TuRuRú", "turu", Case::Mixed(vec![true, false, true, false, true, false]), "TuRu"),
+            ("hUE", "güeh", Case::Mixed(vec![false, true, true]), "gÜEH"),
+        ];
+
+        for (source, target, case, expected) in test_cases {
+            assert_eq!(Case::of(source), case);
+            assert_eq!(case.apply(target), expected);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Words replaced as a whole, written in EPA with `ç` and matched in any case
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dictionary {
+    // Shared, so options can be cloned for every call
+    words: Arc<HashMap<String, String>>,
+}
+
+impl Dictionary {
+    pub fn new() -> Dictionary {
+        Dictionary::default()
+    }
+
+    /// Adds a word, composed like the text the rules see
+    pub fn insert(&mut self, word: &str, replacement: &str) {
+        let word = word.nfc().collect::<String>().to_lowercase();
+        Arc::make_mut(&mut self.words).insert(word, replacement.nfc().collect());
+    }
+
+    /// Adds the words of other, replacing the ones already here
+    pub fn extend(&mut self, other: &Dictionary) {
+        let words = Arc::make_mut(&mut self.words);
+        for (word, replacement) in other.words.iter() {
+            words.insert(word.clone(), replacement.clone());
+        }
+    }
+
+    pub fn get(&self, word: &str) -> Option<&str> {
+        self.words.get(word).map(|w| &w[..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.words.keys().map(|w| &w[..])
+    }
+
+    /// Words with their replacement
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.words.iter().map(|(w, r)| (&w[..], &r[..]))
+    }
+}
+
+impl<'a> FromIterator<(&'a str, &'a str)> for Dictionary {
+    fn from_iter<I: IntoIterator<Item = (&'a str, &'a str)>>(iter: I) -> Dictionary {
+        let mut dictionary = Dictionary::new();
+        for (word, replacement) in iter {
+            dictionary.insert(word, replacement);
+        }
+        dictionary
+    }
+}
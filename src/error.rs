@@ -0,0 +1,136 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use pest::error::InputLocation;
+
+use crate::Rule;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The grammar of a rule can't parse the text
+    Grammar {
+        rule: Rule,
+        /// Byte offset in the input of the rule
+        offset: usize,
+        line: usize,
+        column: usize,
+        snippet: String,
+    },
+    InvalidConfig(String),
+    /// The input isn't what the function takes, like a single word
+    InvalidInput(String),
+    /// An exception dictionary can't be loaded
+    Dictionary {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// Reading or writing a stream failed
+    Io(io::Error),
+}
+
+impl Error {
+    pub(crate) fn grammar(rule: Rule, input: &str, error: pest::error::Error<Rule>) -> Error {
+        let offset = match error.location {
+            InputLocation::Pos(p) => p,
+            InputLocation::Span((p, _)) => p,
+        };
+
+        let (line, column) = position(input, offset);
+        let snippet = input[offset..].lines().next().unwrap_or("")
+            .chars().take(20).collect();
+
+        Error::Grammar { rule, offset, line, column, snippet }
+    }
+
+    // The same error in source, for a text made from it where map tells the
+    // offset in source of every offset
+    pub(crate) fn relocated<F: Fn(usize) -> usize>(self, source: &str, map: F) -> Error {
+        match self {
+            Error::Grammar { rule, offset, snippet, .. } => {
+                let offset = map(offset);
+                let (line, column) = position(source, offset);
+                Error::Grammar { rule, offset, line, column, snippet }
+            },
+            error => error,
+        }
+    }
+
+    // The same error for a text that comes after before
+    pub(crate) fn after(self, before: &str) -> Error {
+        let lines = before.matches('\n').count();
+        let columns = before.rsplit('\n').next().unwrap_or("").chars().count();
+        self.shifted(before.len(), lines, columns)
+    }
+
+    // The same error for a word of text at range, with the snippet going on
+    // after the word
+    pub(crate) fn in_word(self, text: &str, range: Range<usize>) -> Error {
+        match self.after(&text[..range.start]) {
+            Error::Grammar { rule, offset, line, column, snippet } => {
+                let rest = text[range.end..].lines().next().unwrap_or("");
+                let snippet = snippet.chars().chain(rest.chars()).take(20).collect();
+                Error::Grammar { rule, offset, line, column, snippet }
+            },
+            error => error,
+        }
+    }
+
+    // The same error for a text that comes after a number of bytes, line
+    // breaks and characters of its first line
+    pub(crate) fn shifted(self, bytes: usize, lines: usize, columns: usize) -> Error {
+        match self {
+            Error::Grammar { rule, offset, line, column, snippet } => {
+                let column = match line {
+                    1 => column + columns,
+                    _ => column,
+                };
+                Error::Grammar { rule, offset: offset + bytes, line: line + lines, column, snippet }
+            },
+            error => error,
+        }
+    }
+}
+
+// Line and column of a byte offset, counting from 1
+fn position(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+
+    (line, column)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Grammar { rule, line, column, snippet, .. } => {
+                write!(f, "{:?} rule can't parse line {}, column {}: {:?}", rule, line, column, snippet)
+            },
+            Error::InvalidConfig(message) => write!(f, "invalid configuration: {}", message),
+            Error::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            Error::Dictionary { path, line, message } => {
+                write!(f, "{}:{}: {}", path.display(), line, message)
+            },
+            Error::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
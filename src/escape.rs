@@ -0,0 +1,160 @@
+// Pre-tokenisation of text that must reach the output untouched: URLs,
+// e-mail addresses, @mentions and #hashtags.
+
+use crate::protect::Span;
+
+const CLOSING: &[char] = &[')', ']', '}', '!', '?', '"', '\'', '»', '”', '’', '.', ',', ';', ':'];
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+pub(crate) fn is_url(token: &str) -> bool {
+    let lower = token.to_lowercase();
+    ["http://", "https://", "ftp://", "www."].iter()
+        .any(|p| lower.starts_with(p) && lower.len() > p.len())
+}
+
+// Token without the punctuation that closes the sentence around it, keeping
+// a ')' that closes a '(' of the token, like the ones of some URLs
+pub(crate) fn trim_closing(token: &str) -> &str {
+    let mut token = token;
+    while let Some(c) = token.chars().next_back().filter(|c| CLOSING.contains(c)) {
+        if c == ')' && token.matches('(').count() >= token.matches(')').count() {
+            break;
+        }
+        token = &token[..token.len() - c.len_utf8()];
+    }
+
+    token
+}
+
+fn is_email(token: &str) -> bool {
+    let mut parts = token.splitn(2, '@');
+    let user = parts.next().unwrap_or("");
+    let domain = parts.next().unwrap_or("");
+
+    !user.is_empty() && user.chars().all(|c| is_word_char(c) || ".-+%".contains(c)) && !domain.contains('@') &&
+        domain.split('.').count() > 1 &&
+        domain.split('.').all(|p| !p.is_empty() && p.chars().all(|c| is_word_char(c) || c == '-'))
+}
+
+// Byte length of the @mention or #hashtag at the start of token, if any
+fn tag_len(token: &str, mark: char) -> Option<usize> {
+    if !token.starts_with(mark) {
+        return None;
+    }
+
+    let body: usize = token[1..].chars()
+        .take_while(|c| is_word_char(*c))
+        .map(char::len_utf8)
+        .sum();
+
+    match body {
+        0 => None,
+        n => Some(1 + n),
+    }
+}
+
+enum Kind {
+    Link,
+    Hashtag,
+}
+
+// Kind and byte length of the entity at the start of token, if any
+fn entity(token: &str) -> Option<(Kind, usize)> {
+    let trimmed = trim_closing(token);
+    if is_url(trimmed) || is_email(trimmed) {
+        return Some((Kind::Link, trimmed.len()));
+    }
+
+    if let Some(n) = tag_len(token, '@') {
+        return Some((Kind::Link, n));
+    }
+
+    tag_len(token, '#').map(|n| (Kind::Hashtag, n))
+}
+
+// Protects the links of the input, and the hashtags unless their body must be
+// transliterated too.
+pub(crate) fn escape_links(input: &str, hashtags: bool) -> Vec<Span<'_>> {
+    let mut spans = vec![];
+    let mut text_start = 0;
+    let mut offset = 0;
+
+    for token in input.split_inclusive(char::is_whitespace) {
+        let token_start = offset;
+        offset += token.len();
+        let token = token.trim_end();
+
+        // Entities start the token or follow punctuation, like in (@juan)
+        // or mira:https://sevilla.es
+        let mut i = 0;
+        while let Some(c) = token[i..].chars().next() {
+            let after_word = token[..i].chars().next_back().is_some_and(is_word_char);
+            let (kind, len) = match (!after_word).then(|| entity(&token[i..])).flatten() {
+                Some(e) => e,
+                None => {
+                    i += c.len_utf8();
+                    continue;
+                },
+            };
+
+            let start = token_start + i;
+            if start > text_start {
+                spans.push(Span::Text(&input[text_start..start]));
+            }
+
+            let end = start + len;
+            match kind {
+                Kind::Hashtag if hashtags => {
+                    spans.push(Span::Kept(&input[start..start + 1]));
+                    spans.push(Span::Text(&input[start + 1..end]));
+                },
+                _ => spans.push(Span::Kept(&input[start..end])),
+            }
+            text_start = end;
+            i += len;
+        }
+    }
+
+    if text_start < input.len() {
+        spans.push(Span::Text(&input[text_start..]));
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_links() {
+        let input = "Mira https://www.sevilla.es, escribe a info@sevilla.es o a @juan_hidalgo! #SemanaSanta";
+        let expected = vec![
+            Span::Text("Mira "),
+            Span::Kept("https://www.sevilla.es"),
+            Span::Text(", escribe a "),
+            Span::Kept("info@sevilla.es"),
+            Span::Text(" o a "),
+            Span::Kept("@juan_hidalgo"),
+            Span::Text("! "),
+            Span::Kept("#"),
+            Span::Text("SemanaSanta"),
+        ];
+
+        assert_eq!(escape_links(input, true), expected);
+
+        let input = "mira:https://sevilla.es/zona (https://es.wikipedia.org/wiki/Sevilla_(España)).";
+        let expected = vec![
+            Span::Text("mira:"),
+            Span::Kept("https://sevilla.es/zona"),
+            Span::Text(" ("),
+            Span::Kept("https://es.wikipedia.org/wiki/Sevilla_(España)"),
+            Span::Text(")."),
+        ];
+
+        assert_eq!(escape_links(input, false), expected);
+    }
+}
@@ -0,0 +1,271 @@
+// Exception tables of the rules loaded at runtime, that are looked up before
+// the ones of the crate.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use toml::Spanned;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{Dictionary, EpaOptions, Error, EPA_RULES};
+
+/// Words a rule replaces as a whole, before looking at its own exception table
+///
+/// The replacement is written in EPA, like the tables of the crate, and goes
+/// on through the rules after it. Files map the name of each rule to its
+/// words. As TSV, with a line per word with the rule, the word and its
+/// replacement split by tabs, and comment lines starting with `#`.
+///
+/// As TOML, with a table per rule:
+///
+/// ```toml
+/// [h_rule]
+/// hierro = "yerro"
+/// ```
+///
+/// Or as JSON, with an object per rule: `{"h_rule": {"hierro": "yerro"}}`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Exceptions {
+    rules: HashMap<String, Dictionary>,
+}
+
+impl Exceptions {
+    pub fn new() -> Exceptions {
+        Exceptions::default()
+    }
+
+    pub fn insert(&mut self, rule: &str, word: &str, replacement: &str) {
+        self.rules.entry(rule.to_string()).or_default().insert(word, replacement);
+    }
+
+    /// Adds the words of other, replacing the ones already here
+    pub fn extend(&mut self, other: &Exceptions) {
+        for (rule, words) in other.rules.iter() {
+            self.rules.entry(rule.clone()).or_default().extend(words);
+        }
+    }
+
+    /// Words of a rule
+    pub fn get(&self, rule: &str) -> Option<&Dictionary> {
+        self.rules.get(rule)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.values().all(|words| words.is_empty())
+    }
+
+    /// Names of the rules with words
+    pub fn rules(&self) -> impl Iterator<Item = &str> {
+        self.rules.keys().map(|r| &r[..])
+    }
+
+    /// Loads a file, in the format of its extension: `tsv`, `toml` or `json`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Exceptions, Error> {
+        let path = path.as_ref();
+        let parse = match path.extension().and_then(|e| e.to_str()) {
+            Some("tsv") => from_tsv,
+            Some("toml") => from_toml,
+            Some("json") => from_json,
+            _ => return Err(Error::Dictionary {
+                path: path.to_path_buf(),
+                line: 0,
+                message: "unknown format, the extension must be tsv, toml or json".to_string(),
+            }),
+        };
+
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::Dictionary { path: path.to_path_buf(), line: 0, message: e.to_string() })?;
+        parse(&text, path)
+    }
+}
+
+// Checks that every exception of the options is a single word, replaced by
+// text without blanks, as the rules only look at whole words and the stream
+// cuts the output counting its blanks
+pub(crate) fn check(options: &EpaOptions) -> Result<(), Error> {
+    let rule_exceptions = &options.rule_exceptions;
+    let rule_words = rule_exceptions.rules()
+        .flat_map(|r| rule_exceptions.get(r).into_iter().flat_map(|w| w.iter()));
+    for (word, replacement) in options.exceptions.iter().chain(rule_words) {
+        if word.split_word_bounds().count() != 1 || replacement.contains(char::is_whitespace) {
+            return Err(Error::InvalidConfig(format!("exception {:?} isn't a single word", word)));
+        }
+    }
+
+    Ok(())
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+fn check_rule(rule: &str, path: &Path, line: usize) -> Result<(), Error> {
+    match EPA_RULES.iter().any(|(name, _, _)| *name == rule) {
+        true => Ok(()),
+        false => {
+            Err(Error::Dictionary { path: path.to_path_buf(), line, message: format!("unknown rule {:?}", rule) })
+        },
+    }
+}
+
+// Checks and adds a word of the file
+fn add(exceptions: &mut Exceptions, rule: &str, word: &str, replacement: &str, path: &Path, line: usize)
+    -> Result<(), Error> {
+    let error = |message: String| Err(Error::Dictionary { path: path.to_path_buf(), line, message });
+
+    check_rule(rule, path, line)?;
+    if word.split_word_bounds().count() != 1 || word.contains(char::is_whitespace) {
+        return error(format!("{:?} isn't a single word", word));
+    }
+    if replacement.is_empty() || replacement.contains(char::is_whitespace) {
+        return error(format!("replacement {:?} of {:?} isn't a single word", replacement, word));
+    }
+    if exceptions.get(rule).is_some_and(|words| words.get(&word.to_lowercase()).is_some()) {
+        return error(format!("{:?} twice for {}", word, rule));
+    }
+
+    exceptions.insert(rule, word, replacement);
+    Ok(())
+}
+
+fn from_tsv(text: &str, path: &Path) -> Result<Exceptions, Error> {
+    let mut exceptions = Exceptions::new();
+
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        match line.split('\t').collect::<Vec<_>>()[..] {
+            [rule, word, replacement] => {
+                add(&mut exceptions, rule.trim(), word.trim(), replacement.trim(), path, i + 1)?
+            },
+            _ => return Err(Error::Dictionary {
+                path: path.to_path_buf(),
+                line: i + 1,
+                message: "expected a rule, a word and its replacement, split by tabs".to_string(),
+            }),
+        }
+    }
+
+    Ok(exceptions)
+}
+
+fn from_toml(text: &str, path: &Path) -> Result<Exceptions, Error> {
+    type Tables = BTreeMap<Spanned<String>, BTreeMap<Spanned<String>, Spanned<String>>>;
+
+    let tables: Tables = toml::from_str(text).map_err(|e| Error::Dictionary {
+        path: path.to_path_buf(),
+        line: e.span().map_or(1, |s| line_of(text, s.start)),
+        message: e.message().to_string(),
+    })?;
+
+    let mut exceptions = Exceptions::new();
+    for (rule, words) in tables.iter() {
+        check_rule(rule.get_ref(), path, line_of(text, rule.span().start))?;
+        for (word, replacement) in words.iter() {
+            let line = line_of(text, word.span().start);
+            add(&mut exceptions, rule.get_ref(), word.get_ref(), replacement.get_ref(), path, line)?;
+        }
+    }
+
+    Ok(exceptions)
+}
+
+// A JSON object that, like the TOML tables, can't have a key twice, where
+// serde_json would keep the last one
+struct Unique<V>(BTreeMap<String, V>);
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for Unique<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct UniqueVisitor<V>(std::marker::PhantomData<V>);
+
+        impl<'de, V: Deserialize<'de>> Visitor<'de> for UniqueVisitor<V> {
+            type Value = Unique<V>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "an object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut values = BTreeMap::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    if values.contains_key(&key) {
+                        return Err(de::Error::custom(format!("duplicate key {:?}", key)));
+                    }
+                    let value = map.next_value()?;
+                    values.insert(key, value);
+                }
+                Ok(Unique(values))
+            }
+        }
+
+        deserializer.deserialize_map(UniqueVisitor(std::marker::PhantomData))
+    }
+}
+
+fn from_json(text: &str, path: &Path) -> Result<Exceptions, Error> {
+    let Unique(tables): Unique<Unique<String>> = serde_json::from_str(text)
+        .map_err(|e| Error::Dictionary { path: path.to_path_buf(), line: e.line(), message: e.to_string() })?;
+
+    // serde_json doesn't keep where the values are, so the lines are the
+    // ones of the keys, looked up in the text
+    let find = |key: &str, from: usize| {
+        let quoted = serde_json::to_string(key).unwrap_or_default();
+        text[from..].find(&quoted).map_or(from, |i| from + i)
+    };
+
+    let mut exceptions = Exceptions::new();
+    for (rule, Unique(words)) in tables.iter() {
+        let start = find(rule, 0);
+        check_rule(rule, path, line_of(text, start))?;
+        for (word, replacement) in words.iter() {
+            let line = line_of(text, find(word, start));
+            add(&mut exceptions, rule, word, replacement, path, line)?;
+        }
+    }
+
+    Ok(exceptions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(result: Result<Exceptions, Error>) -> usize {
+        match result {
+            Err(Error::Dictionary { line, .. }) => line,
+            other => panic!("Wrong exceptions {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_exceptions() {
+        let path = Path::new("exceptions");
+        let tsv = "# rule\tword\treplacement\nh_rule\thierro\tyerro\n\n   # comentario\nword_ending_rule\tBeti\tBéti\n";
+        let toml = "[h_rule]\nhierro = \"yerro\"\n\n[word_ending_rule]\nBeti = \"Béti\"\n";
+        let json = "{\n  \"h_rule\": {\"hierro\": \"yerro\"},\n  \"word_ending_rule\": {\"Beti\": \"Béti\"}\n}";
+
+        let mut expected = Exceptions::new();
+        expected.insert("h_rule", "hierro", "yerro");
+        expected.insert("word_ending_rule", "beti", "Béti");
+        assert_eq!(from_tsv(tsv, path).expect("Wrong exceptions"), expected);
+        assert_eq!(from_toml(toml, path).expect("Wrong exceptions"), expected);
+        assert_eq!(from_json(json, path).expect("Wrong exceptions"), expected);
+
+        assert_eq!(line(from_tsv("h_rule\thierro\tyerro\nh_rule\thierro\n", path)), 2);
+        assert_eq!(line(from_tsv("h_rule\thierro\tyerro\nh_rule\tHierro\tierro\n", path)), 2);
+        assert_eq!(line(from_tsv("\nz_rule\tzapato\tsapato\n", path)), 2);
+        assert_eq!(line(from_toml("[h_rule]\nhierro = \"yerro\"\n\n[z_rule]\n", path)), 4);
+        assert_eq!(line(from_toml("[h_rule]\nhierro = \"yerro\"\n\"la hiedra\" = \"la yedra\"\n", path)), 3);
+        assert_eq!(line(from_toml("[h_rule]\nhierro = 1\n", path)), 2);
+        assert_eq!(line(from_json("{\n\"h_rule\": {\n\"hierro\": \"yerro\",\n\"hiedra\": \"ye dra\"}}", path)), 4);
+        assert_eq!(line(from_json("{\"h_rule\":\n{\"hierro\": }}", path)), 2);
+        assert_eq!(line(from_json("{\"h_rule\": {\"hierro\": \"yerro\",\n\"hierro\": \"ierro\"}}", path)), 2);
+        assert_eq!(line(from_json("{\"h_rule\": {},\n\"h_rule\": {}}", path)), 2);
+        assert_eq!(line(Exceptions::load("exceptions.csv")), 0);
+        assert_eq!(line(Exceptions::load("missing-exceptions.toml")), 0);
+    }
+}
@@ -0,0 +1,187 @@
+// Explanations of what the rules did to a word, from its trace.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::pipeline::{exception_table, Pipeline};
+use crate::syllables::{self, Stress};
+use crate::{defs, EpaOptions, Error, Rule, Step};
+
+/// A change a step of the pipeline made to a word
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transformation {
+    pub step: String,
+    /// Grammar rule that fired, `None` for exceptions and text rewritten
+    /// without the grammar
+    pub rule: Option<Rule>,
+    /// Exception table the replacement comes from
+    pub exception: Option<&'static str>,
+    pub description: &'static str,
+    /// Text of the step input that changed
+    pub input: String,
+    pub output: String,
+}
+
+impl fmt::Display for Transformation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.exception {
+            Some(table) => write!(f, "exception: {}", table),
+            None => write!(f, "{}: {}", self.step, self.description),
+        }
+    }
+}
+
+fn has_tilde(text: &str) -> bool {
+    text.to_lowercase().contains(['á', 'é', 'í', 'ó', 'ú'])
+}
+
+fn describe(rule: Rule, word: &str) -> &'static str {
+    // The vowel before the last letter and the stress, for the word endings
+    let n = word.chars().count();
+    let vowel: String = word.chars().skip(n.saturating_sub(2)).take(1).collect();
+    let last_stressed = syllables::stress(word) == Some(Stress::Aguda);
+
+    match rule {
+        Rule::initial_h => "mute initial h",
+        Rule::inner_h => "mute h",
+        Rule::hue => "hue written güe",
+        Rule::hua => "hua written gua",
+        Rule::initial_x => "initial x written ç",
+        Rule::inner_vowel_x => "x between vowels written çç",
+        Rule::CH => "ch written x",
+        Rule::BUE | Rule::BUE1 => "bue written gue",
+        Rule::GJV => "g or j before a vowel written as the VVF",
+        Rule::GUE => "mute u of gue or gui",
+        Rule::GUEd => "güe or güi written with u",
+        Rule::NV => "nv written mb",
+        Rule::V => "v written b",
+        Rule::LL => "ll written y",
+        Rule::L => "l before a consonant written r",
+        Rule::PSIC | Rule::PSEUD => "mute p of psic or pseud",
+        Rule::ZSv => "z or s before a vowel written ç",
+        Rule::Cv => "c before e or i written ç",
+        Rule::ENDING_D if !last_stressed => "ENDING_D on unstressed vowel",
+        Rule::ENDING_D => "ENDING_D on stressed vowel",
+        Rule::ENDING_S if has_tilde(&vowel) => "ENDING_S on stressed vowel",
+        Rule::ENDING_S => "ENDING_S on unstressed vowel",
+        Rule::ENDING_CONS if !last_stressed => "ENDING_CONS on unstressed vowel",
+        Rule::ENDING_CONS => "ENDING_CONS on stressed vowel",
+        Rule::ENDING_PS => "ENDING_PS, mute ps",
+        Rule::INTER_D => "INTER_D, d between vowels of -ado or -ido",
+        Rule::DIGRAPH_EXP_LSTRST => "lst or rst written rtt",
+        Rule::DIGRAPH_EXP_TRANS => "ns or st of trans, tras, pos or post doubles the next consonant",
+        Rule::DIGRAPH_EXP_BDNR => "bs, ds, ns or rs doubles the next consonant",
+        Rule::DIGRAPH_EXP_L => "consonant before l written l-l",
+        Rule::GEN_DIGRAPH => "consonant cluster written as a double consonant",
+        Rule::ENDING_L => "final l before a consonant written r",
+        _ => "rewritten",
+    }
+}
+
+// Exception table of the word endings, that the grammar rule checks first
+fn word_ending_table(rule: Rule) -> Option<(&'static str, &'static HashMap<&'static str, &'static str>)> {
+    match rule {
+        Rule::ENDING_D => Some(("WORDEND_D_RULES_EXCEPT", &defs::WORDEND_D_RULES_EXCEPT)),
+        Rule::ENDING_S => Some(("WORDEND_S_RULES_EXCEPT", &defs::WORDEND_S_RULES_EXCEPT)),
+        Rule::ENDING_CONS => Some(("WORDEND_CONST_RULES_EXCEPT", &defs::WORDEND_CONST_RULES_EXCEPT)),
+        Rule::INTER_D => Some(("WORDEND_D_INTERVOWEL_RULES_EXCEPT", &defs::WORDEND_D_INTERVOWEL_RULES_EXCEPT)),
+        _ => None,
+    }
+}
+
+fn transformations(steps: Vec<Step>, options: &EpaOptions) -> Vec<Transformation> {
+    let mut transformations = vec![];
+
+    for step in steps {
+        for m in step.matches {
+            let input = step.input[m.span].to_string();
+            let lword = input.to_lowercase();
+
+            let (exception, description) = match m.rule {
+                Some(rule) => {
+                    let table = word_ending_table(rule)
+                        .filter(|(_, map)| map.get(&lword[..]).is_some());
+                    (table.map(|(name, _)| name), describe(rule, &input))
+                },
+                None if options.rule_exceptions.get(&step.name).is_some_and(|w| w.get(&lword).is_some()) => {
+                    (Some("user exceptions"), "word of the user exceptions of the rule")
+                },
+                None if step.name == "vaf_output" && (input == "ç" || input == "Ç") => {
+                    (None, "ç written as the chosen VAF")
+                },
+                None if step.name == "vaf_output" && (input == "h" || input == "H") => {
+                    (None, "h written as the chosen VVF")
+                },
+                None if step.name == "vaf_output" => (Some("user dictionary"), "word of the user dictionary"),
+                None if step.name == "nfd_output" => (None, "written decomposed (NFD)"),
+                None => match exception_table(&step.name) {
+                    Some((name, _)) => (Some(name), "word of an exception table"),
+                    None => (None, "rewritten"),
+                },
+            };
+
+            transformations.push(Transformation {
+                step: step.name.clone(),
+                rule: m.rule,
+                exception,
+                description,
+                input,
+                output: m.output,
+            });
+        }
+    }
+
+    transformations
+}
+
+// Transliterates a single word, with the transformations every step made
+pub(crate) fn transliterate_word(pipeline: &Pipeline, word: &str, options: &EpaOptions)
+    -> Result<(String, Vec<Transformation>), Error> {
+    let word = word.trim();
+    if word.is_empty() || word.contains(char::is_whitespace) {
+        return Err(Error::InvalidInput(format!("{:?} isn't a single word", word)));
+    }
+
+    let steps = pipeline.trace(word, options)?;
+    let output = steps.last().map_or_else(String::new, |s| s.output.clone());
+
+    Ok((output, transformations(steps, options)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{transliterate_word, transliterate_word_with, EpaOptions, Error, Vaf};
+
+    fn explain(word: &str, options: &EpaOptions) -> (String, Vec<String>) {
+        let (output, transformations) = transliterate_word_with(word, options).expect("Wrong parser");
+        (output, transformations.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn test_transliterate_word() {
+        let options = EpaOptions { vaf: Vaf::S, ..Default::default() };
+        assert_eq!(explain("Hachas", &options), ("Axâ".to_string(), vec![
+            "h_rule: mute initial h".to_string(),
+            "ch_rule: ch written x".to_string(),
+            "word_ending_rule: ENDING_S on unstressed vowel".to_string(),
+        ]));
+        assert_eq!(explain("compás", &options), ("compâh".to_string(), vec![
+            "word_ending_rule: ENDING_S on stressed vowel".to_string(),
+        ]));
+        assert_eq!(explain("todo", &options), ("tó".to_string(), vec![
+            "exception: ENDING_RULES_EXCEPTION".to_string(),
+        ]));
+        assert_eq!(explain("zapato", &options), ("sapato".to_string(), vec![
+            "vaf_rule: z or s before a vowel written ç".to_string(),
+            "vaf_output: ç written as the chosen VAF".to_string(),
+        ]));
+
+        assert_eq!(explain(" zapato\n", &options).0, "sapato");
+        for input in ["dos palabras", " "].iter() {
+            match transliterate_word(input) {
+                Err(Error::InvalidInput(_)) => {},
+                other => panic!("Wrong word {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+// HTML documents, where the rules only see the text of the elements, and the
+// title and alt attributes when asked, and the rest is written back byte by
+// byte.
+
+use std::ops::Range;
+
+use crate::markup::{self, Text, Writer};
+use crate::pipeline::Pipeline;
+use crate::{EpaOptions, Error};
+
+/// What the HTML mode transliterates besides the text of the elements
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HtmlOptions {
+    /// Transliterate the title and alt attributes too
+    pub attributes: bool,
+}
+
+// Elements whose content is never transliterated
+const SKIPPED: &[&str] = &["script", "style", "code"];
+// Elements with text but no tags inside
+const TEXT_ONLY: &[&str] = &["script", "style", "textarea", "title"];
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
+// Elements in the middle of the text, that can even split a word
+const INLINE: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "data", "del", "dfn", "em", "i", "ins", "kbd", "mark", "q", "s",
+    "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var",
+];
+const ATTRIBUTES: &[&str] = &["title", "alt"];
+
+struct Attribute {
+    name: String,
+    // Without the quotes
+    value: Range<usize>,
+    quote: Option<char>,
+}
+
+enum Token {
+    Text(Range<usize>),
+    Start { name: String, attributes: Vec<Attribute>, closed: bool, range: Range<usize> },
+    End { name: String, range: Range<usize> },
+    // Comments, doctypes and the like
+    Other(Range<usize>),
+}
+
+fn find_from(input: &str, from: usize, pattern: &str) -> Option<usize> {
+    input[from..].find(pattern).map(|i| from + i)
+}
+
+// End of the run of bytes from start not matched by stop
+fn scan<F: Fn(u8) -> bool>(input: &str, start: usize, stop: F) -> usize {
+    input.as_bytes()[start..].iter().position(|b| stop(*b)).map_or(input.len(), |i| start + i)
+}
+
+fn tag_name(input: &str, start: usize) -> (String, usize) {
+    let end = scan(input, start, |b| b.is_ascii_whitespace() || b == b'/' || b == b'>');
+    (input[start..end].to_ascii_lowercase(), end)
+}
+
+// Attributes of a start tag from start, with where the tag ends and whether
+// it closes itself
+fn attributes(input: &str, mut i: usize) -> (Vec<Attribute>, usize, bool) {
+    let bytes = input.as_bytes();
+    let mut attributes = vec![];
+
+    loop {
+        i = scan(input, i, |b| !b.is_ascii_whitespace());
+        match bytes.get(i) {
+            None => return (attributes, input.len(), false),
+            Some(b'>') => return (attributes, i + 1, false),
+            Some(b'/') if bytes.get(i + 1) == Some(&b'>') => return (attributes, i + 2, true),
+            Some(b'/') => {
+                i += 1;
+                continue;
+            },
+            _ => {},
+        }
+
+        let end = scan(input, i, |b| b.is_ascii_whitespace() || b == b'/' || b == b'>' || b == b'=');
+        let name = input[i..end.max(i + 1)].to_ascii_lowercase();
+        i = scan(input, end.max(i + 1), |b| !b.is_ascii_whitespace());
+        if bytes.get(i) != Some(&b'=') {
+            attributes.push(Attribute { name, value: i..i, quote: None });
+            continue;
+        }
+
+        i = scan(input, i + 1, |b| !b.is_ascii_whitespace());
+        let (value, quote) = match bytes.get(i) {
+            Some(q @ (b'"' | b'\'')) => {
+                let end = scan(input, i + 1, |b| b == *q);
+                ((i + 1)..end, Some(*q as char))
+            },
+            _ => (i..scan(input, i, |b| b.is_ascii_whitespace() || b == b'>'), None),
+        };
+        i = match quote {
+            Some(_) => (value.end + 1).min(input.len()),
+            None => value.end,
+        };
+        attributes.push(Attribute { name, value, quote });
+    }
+}
+
+fn tokens(input: &str) -> Vec<Token> {
+    let bytes = input.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < input.len() {
+        if bytes[i] != b'<' {
+            let end = scan(input, i + 1, |b| b == b'<');
+            tokens.push(Token::Text(i..end));
+            i = end;
+            continue;
+        }
+
+        let next = bytes.get(i + 1).cloned().unwrap_or(b' ');
+        let token = match next {
+            b'!' if input[i..].starts_with("<!--") => {
+                let end = find_from(input, i + 4, "-->").map_or(input.len(), |e| e + 3);
+                Token::Other(i..end)
+            },
+            b'!' | b'?' => Token::Other(i..find_from(input, i, ">").map_or(input.len(), |e| e + 1)),
+            b'/' if bytes.get(i + 2).is_some_and(u8::is_ascii_alphabetic) => {
+                let (name, end) = tag_name(input, i + 2);
+                let end = find_from(input, end, ">").map_or(input.len(), |e| e + 1);
+                Token::End { name, range: i..end }
+            },
+            n if n.is_ascii_alphabetic() => {
+                let (name, end) = tag_name(input, i + 1);
+                let (attributes, end, closed) = attributes(input, end);
+                Token::Start { name, attributes, closed, range: i..end }
+            },
+            // A < that doesn't start a tag is text
+            _ => Token::Text(i..i + 1),
+        };
+
+        i = match &token {
+            Token::Text(range) | Token::Other(range) | Token::End { range, .. } | Token::Start { range, .. } => {
+                range.end
+            },
+        };
+
+        // The text of elements like script goes on until their end tag
+        if let Token::Start { name, closed: false, .. } = &token {
+            if TEXT_ONLY.contains(&&name[..]) {
+                let end = input[i..].to_ascii_lowercase().find(&format!("</{}", name)).map_or(input.len(), |e| i + e);
+                tokens.push(token);
+                if end > i {
+                    tokens.push(Token::Text(i..end));
+                }
+                i = end;
+                continue;
+            }
+        }
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+// An open element and why its content is kept
+struct Element {
+    name: String,
+    // script, style or code
+    code: bool,
+    // Written in another language
+    foreign: bool,
+}
+
+pub(crate) fn transliterate(pipeline: &Pipeline, input: &str, options: &EpaOptions, html_options: &HtmlOptions)
+    -> Result<String, Error> {
+    let mut writer = Writer::new(pipeline, input, options);
+    let mut stack: Vec<Element> = vec![];
+    // Text that goes on through inline tags, so the rules see its words whole
+    let mut run = Text::new();
+
+    for token in tokens(input) {
+        let kept = stack.last().is_some_and(|e| e.code || e.foreign);
+
+        match token {
+            Token::Text(range) if !kept => run.push_html(input, range),
+            Token::Start { name, attributes, closed, range } => {
+                let parent = stack.last();
+                let lang = attributes.iter().find(|a| a.name == "lang" || a.name == "xml:lang");
+                let element = Element {
+                    code: SKIPPED.contains(&&name[..]) || parent.is_some_and(|p| p.code),
+                    foreign: match lang {
+                        Some(lang) => !markup::is_spanish(&input[lang.value.clone()]),
+                        None => parent.is_some_and(|p| p.foreign),
+                    },
+                    name,
+                };
+                let kept = element.code || element.foreign;
+
+                let attributes: Vec<&Attribute> = attributes.iter()
+                    .filter(|a| html_options.attributes && !kept && ATTRIBUTES.contains(&&a.name[..]))
+                    .collect();
+                if INLINE.contains(&&element.name[..]) && !kept && attributes.is_empty() {
+                    run.push_markup(range);
+                } else {
+                    writer.write(&run, |t| markup::escape(t, None))?;
+                    run = Text::new();
+                    for attribute in attributes {
+                        let mut value = Text::new();
+                        value.push_html(input, attribute.value.clone());
+                        writer.write(&value, |t| markup::escape(t, attribute.quote.or(Some('"'))))?;
+                    }
+                }
+
+                if !closed && !VOID.contains(&&element.name[..]) {
+                    stack.push(element);
+                }
+            },
+            Token::End { name, range } => {
+                let inline = INLINE.contains(&&name[..]) && !kept;
+                if let Some(i) = stack.iter().rposition(|e| e.name == name) {
+                    stack.truncate(i);
+                }
+
+                match inline && !stack.last().is_some_and(|e| e.code || e.foreign) {
+                    true => run.push_markup(range),
+                    false => {
+                        writer.write(&run, |t| markup::escape(t, None))?;
+                        run = Text::new();
+                    },
+                }
+            },
+            _ => {
+                writer.write(&run, |t| markup::escape(t, None))?;
+                run = Text::new();
+            },
+        }
+    }
+
+    writer.write(&run, |t| markup::escape(t, None))?;
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{epa_html, EpaOptions, HtmlOptions};
+
+    fn html(input: &str, attributes: bool) -> String {
+        epa_html(input, &EpaOptions::default(), &HtmlOptions { attributes }).expect("Wrong parser")
+    }
+
+    #[test]
+    fn test_html() {
+        let input = "<!DOCTYPE html>\n<div class=\"hola\" title=\"Hola\"><p>El <b>hierro</b> de la\n  \
+                     <i>Z</i>aragoza &aacute;rabe&nbsp;y jerez.<br>Hola</p>\n\
+                     <script>var hola = \"hierro\";</script><code>zapato</code>\
+                     <p lang=\"en\">hello <span lang=\"es-ES\">zapato</span></p>\
+                     <!-- hierro --><img alt='Zapato de «Hierro»'></div>";
+
+        assert_eq!(html(input, false), "<!DOCTYPE html>\n<div class=\"hola\" title=\"Hola\"><p>El <b>ierro</b> de \
+                                        la\n  <i>Ç</i>aragoça &aacute;rabe&nbsp;y herêh.<br>Ola</p>\n\
+                                        <script>var hola = \"hierro\";</script><code>zapato</code>\
+                                        <p lang=\"en\">hello <span lang=\"es-ES\">çapato</span></p>\
+                                        <!-- hierro --><img alt='Zapato de «Hierro»'></div>");
+
+        let output = html(input, true);
+        assert!(output.starts_with("<!DOCTYPE html>\n<div class=\"hola\" title=\"Ola\">"));
+        assert!(output.ends_with("<img alt='Çapato de «Ierro»'></div>"));
+
+        assert_eq!(html("<p>Cádiz &amp; Málaga</p>", false), "<p>Cádî &amp; Málaga</p>");
+        assert_eq!(html("<p>Hola&#0; &#7;hierro &#xD800; Xavi</p>", false), "<p>Ola&#0; &#7;ierro &#xD800; Çabi</p>");
+    }
+}
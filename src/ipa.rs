@@ -0,0 +1,221 @@
+// Broad IPA transcription of the andaluz pronunciation, read from the EPA
+// spelling, that already writes what the rules know: the aspirated h, ç for
+// /s/, x for /ʃ/, the vowels of the lost final consonants with a circumflex
+// and the geminated consonants doubled.
+
+use std::str::FromStr;
+
+use crate::syllables::{self, Kind, Unit};
+use crate::Error;
+
+/// Sound of the EPA `ç`, the one of `c`, `z` and `s`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sibilant {
+    /// /s/
+    #[default]
+    Seseo,
+    /// /θ/
+    Ceceo,
+}
+
+impl FromStr for Sibilant {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Sibilant, Error> {
+        match &s.to_lowercase()[..] {
+            "seseo" => Ok(Sibilant::Seseo),
+            "ceceo" => Ok(Sibilant::Ceceo),
+            _ => Err(Error::InvalidConfig(format!("unknown sibilant {:?}", s))),
+        }
+    }
+}
+
+/// Vowels before a lost final consonant, the ones EPA writes with a circumflex
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Vowels {
+    /// Like any other vowel, as in Western Andalusia
+    #[default]
+    Western,
+    /// Open, as in Eastern Andalusia
+    Eastern,
+}
+
+impl FromStr for Vowels {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Vowels, Error> {
+        match &s.to_lowercase()[..] {
+            "western" => Ok(Vowels::Western),
+            "eastern" => Ok(Vowels::Eastern),
+            _ => Err(Error::InvalidConfig(format!("unknown vowels {:?}", s))),
+        }
+    }
+}
+
+/// Accent of the IPA transcription
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IpaOptions {
+    pub sibilant: Sibilant,
+    pub vowels: Vowels,
+}
+
+fn vowel(c: char, options: &IpaOptions) -> &'static str {
+    let eastern = options.vowels == Vowels::Eastern;
+    match c {
+        'a' | 'á' => "a",
+        'e' | 'é' => "e",
+        'i' | 'í' => "i",
+        'o' | 'ó' => "o",
+        'u' | 'ú' | 'ü' => "u",
+        'â' if eastern => "æ",
+        'ê' if eastern => "ɛ",
+        'î' if eastern => "ɪ",
+        'ô' if eastern => "ɔ",
+        'û' if eastern => "ʊ",
+        'â' => "a",
+        'ê' => "e",
+        'î' => "i",
+        'ô' => "o",
+        _ => "u",
+    }
+}
+
+// The sounds of a lowercase EPA word, as units to split in syllables and
+// their IPA
+fn sounds(word: &str, options: &IpaOptions) -> (Vec<Unit>, Vec<String>) {
+    let chars: Vec<char> = word.chars().collect();
+    let sibilant = match options.sibilant {
+        Sibilant::Seseo => "s",
+        Sibilant::Ceceo => "θ",
+    };
+
+    let mut units = vec![];
+    let mut ipa = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let prev = i.checked_sub(1).map(|p| chars[p]);
+        let next = chars.get(i + 1).cloned();
+        let front = |c: Option<char>| c.is_some_and(|c| "eiéíêî".contains(c));
+
+        // The hyphen of l-l only keeps both l apart
+        if c == '-' {
+            i += 1;
+            continue;
+        }
+
+        let (kind, len, sound) = match (c, next) {
+            ('l', Some('l')) => (Kind::Consonant, 2, "ʝ"),
+            ('r', Some('r')) => (Kind::Consonant, 2, "r"),
+            ('c', Some('h')) => (Kind::Consonant, 2, "tʃ"),
+            ('q', Some('u')) if front(chars.get(i + 2).cloned()) => (Kind::Consonant, 2, "k"),
+            ('y', n) if n.is_some_and(syllables::is_vowel) => (Kind::Consonant, 1, "ʝ"),
+            ('y', _) => (Kind::Weak, 1, "i"),
+            (c, _) if syllables::is_vowel(c) => (syllables::vowel_kind(c), 1, vowel(c, options)),
+            ('r', _) if prev.is_none_or(|p| "lns".contains(p)) => (Kind::Consonant, 1, "r"),
+            ('r', _) => (Kind::Consonant, 1, "ɾ"),
+            ('c', n) if front(n) => (Kind::Consonant, 1, sibilant),
+            ('ç' | 'z' | 's', _) => (Kind::Consonant, 1, sibilant),
+            ('c' | 'k' | 'q', _) => (Kind::Consonant, 1, "k"),
+            ('b' | 'v', _) => (Kind::Consonant, 1, "b"),
+            ('g', _) => (Kind::Consonant, 1, "ɡ"),
+            ('h' | 'j', _) => (Kind::Consonant, 1, "h"),
+            ('ñ', _) => (Kind::Consonant, 1, "ɲ"),
+            ('x', _) => (Kind::Consonant, 1, "ʃ"),
+            _ => (Kind::Consonant, 1, ""),
+        };
+
+        let text: String = chars[i..i + len].iter().collect();
+        ipa.push(match sound {
+            "" => text.clone(),
+            sound => sound.to_string(),
+        });
+        units.push(Unit { kind, text });
+        i += len;
+    }
+
+    (units, ipa)
+}
+
+// Transcribes a lowercase EPA word, marking the stress when it has more than
+// one syllable
+fn word(word: &str, options: &IpaOptions) -> String {
+    let (units, mut ipa) = sounds(word, options);
+    let starts = syllables::split(&units);
+
+    // i and u before a vowel of their syllable are glides
+    for i in 0..units.len().saturating_sub(1) {
+        let glide = units[i].kind == Kind::Weak && units[i + 1].kind != Kind::Consonant
+            && !starts.contains(&(i + 1));
+        match &ipa[i][..] {
+            "i" if glide => ipa[i] = "j".to_string(),
+            "u" if glide => ipa[i] = "w".to_string(),
+            _ => {},
+        }
+    }
+
+    let stressed = match starts.len() {
+        0 | 1 => None,
+        _ => syllables::stressed(&units, &starts).map(|s| starts[s]),
+    };
+
+    ipa.into_iter().enumerate()
+        .map(|(i, sound)| match Some(i) == stressed {
+            true => format!("ˈ{}", sound),
+            false => sound,
+        }).collect()
+}
+
+/// Transcribes EPA text written with `ç` and `h`, keeping what isn't a word
+pub(crate) fn transcribe(epa: &str, options: &IpaOptions) -> String {
+    let chars: Vec<char> = epa.chars().collect();
+    let mut output = String::with_capacity(epa.len());
+    let mut current = String::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        // The l-l of the EPA is a geminated l
+        let geminated = *c == '-' && i > 0 && "lL".contains(chars[i - 1])
+            && chars.get(i + 1).is_some_and(|n| "lL".contains(*n));
+
+        if c.is_alphabetic() || geminated {
+            current.extend(c.to_lowercase());
+            continue;
+        }
+        if !current.is_empty() {
+            output += &word(&current, options);
+            current.clear();
+        }
+        output.push(*c);
+    }
+    if !current.is_empty() {
+        output += &word(&current, options);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ipa_with, EpaOptions, IpaOptions, Sibilant, Vowels};
+
+    fn ipa(input: &str, sibilant: Sibilant, vowels: Vowels) -> String {
+        ipa_with(input, &EpaOptions::default(), &IpaOptions { sibilant, vowels }).expect("Wrong parser")
+    }
+
+    #[test]
+    fn test_ipa() {
+        let input = "Lleva un Guijarrito el ABuelo, ¡Qué Bueno!";
+        assert_eq!(ipa(input, Sibilant::Seseo, Vowels::Western), "ˈʝeba un ɡihaˈrito el aˈɡwelo, ¡ke ˈɡweno!");
+
+        let input = "Los chicos de Madrid transportan caracteres con el reloj";
+        assert_eq!(ipa(input, Sibilant::Seseo, Vowels::Western),
+                   "lo ˈʃiko de maˈdɾih tɾapˈpoɾtan kaɾatˈteɾe kon el reˈloh");
+        assert_eq!(ipa(input, Sibilant::Ceceo, Vowels::Eastern),
+                   "lɔ ˈʃikɔ de maˈdɾɪh tɾæpˈpoɾtan kaɾætˈteɾɛ kon el reˈlɔh");
+
+        let input = "El aislante asfixió al pseudoescritor de la ciudad";
+        assert_eq!(ipa(input, Sibilant::Ceceo, Vowels::Western),
+                   "el ailˈlante affiθˈθjo aɾ θeudoekkɾiˈtoh de la θjuˈda");
+    }
+}
@@ -1,14 +1,56 @@
-use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::str::FromStr;
 
+use lazy_static::lazy_static;
 use pest::Parser;
 use pest_derive::Parser;
 use pest::iterators::Pair;
 
 use unicode_segmentation::UnicodeSegmentation;
 
-use failure::Error;
-
+mod align;
+mod case;
 mod defs;
+mod dictionary;
+mod error;
+mod escape;
+mod exceptions;
+mod explain;
+mod html;
+mod ipa;
+mod markdown;
+mod markup;
+mod marks;
+mod normalize;
+mod po;
+mod protect;
+mod resources;
+mod stream;
+mod subtitles;
+mod syllables;
+mod trace;
+mod transliterator;
+
+pub mod pipeline;
+
+use case::Case;
+use marks::Marks;
+use syllables::Stress;
+
+pub use align::Alignment;
+pub use dictionary::Dictionary;
+pub use error::Error;
+pub use exceptions::Exceptions;
+pub use explain::Transformation;
+pub use html::HtmlOptions;
+pub use ipa::{IpaOptions, Sibilant, Vowels};
+pub use normalize::Normalization;
+pub use po::PoOptions;
+pub use resources::Resource;
+pub use subtitles::Subtitles;
+pub use trace::{Match, Step};
+pub use transliterator::{Transliterator, TransliteratorBuilder};
 
 macro_rules! chars {
     ($input: expr) => {
@@ -37,69 +79,58 @@ macro_rules! len {
 }
 
 macro_rules! rule {
-    ($rule: expr, $input: expr, $( $($t: pat)|* => $r: expr ),* ) => {{
-        let map: Option<HashMap<&str, &str>> = None;
-        rule!($rule, $input, map, $( $($t)|* => $r ),*)
+    ($rule: expr, $input: expr, $options: expr, $trace: expr,
+     $( $($t: pat)|* => $r: expr ),* ) => {{
+        rule!($rule, $input, $options, $trace, None, $( $($t)|* => $r ),*)
     }};
-    ($rule: expr, $input: expr, $map: expr, $( $($t: pat)|* => $r: expr ),* ) => {{
-        let (repl, input) = match $map {
-            Some(ref m) => replace_exceptions($input, m),
-            None => (vec![], $input.to_string())
-        };
-
-        let pairs = AndaluhParser::parse($rule, &input)?;
-        let mut output: Vec<String> = vec![];
-
-        for pair in pairs {
-            let chunk = match pair.as_rule() {
-                $( $($t)|* => {
-                    $r(pair)
-                } ),*
-                _ => {
-                    String::from(pair.as_str())
-                },
-            };
-            output.push(chunk);
-        }
+    ($rule: expr, $input: expr, $options: expr, $trace: expr, $map: expr,
+     $( $($t: pat)|* => $r: expr ),* ) => {{
+        let spans = protect::spans($input, $options, $map);
 
-        let mut outstr = output.join("");
+        protect::transliterate(spans, $trace, |input, mut trace: Trace| {
+            let pairs = AndaluhParser::parse($rule, input)
+                .map_err(|e| Error::grammar($rule, input, e))?;
+            let mut output: Vec<String> = vec![];
 
-        if $map.is_some() {
-            outstr = replace_exceptions_back(&outstr, repl);
-        }
+            for pair in pairs {
+                let span = pair.as_span();
+                let chunk = match pair.as_rule() {
+                    $( $($t)|* => {
+                        let fired = trace.as_ref().map(|_| fired_rule(&pair));
+                        let chunk = $r(pair);
+                        if let (Some(t), Some(rule)) = (trace.as_deref_mut(), fired) {
+                            t.push(Match {
+                                rule: Some(rule),
+                                span: span.start()..span.end(),
+                                output: chunk.clone(),
+                            });
+                        }
+                        chunk
+                    } ),*
+                    _ => {
+                        String::from(pair.as_str())
+                    },
+                };
+                output.push(chunk);
+            }
 
-        Ok(outstr)
+            Ok(output.join(""))
+        })
     }}
 }
 
-fn replace_exceptions(input: &str, m: &HashMap<&str, &str>)
-    -> (Vec<(String, String)>, String) {
-    let mut map = vec![];
-    let mut out = input.to_string();
-    for (i, word) in input.split_whitespace().enumerate() {
-        let lword = word.to_string().to_lowercase();
-        if !m.contains_key(&lword[..]) {
-            continue;
-        }
-
-        let repl = format!("AND_{}", i);
-        out = out.replace(word, &repl);
-        map.push((repl, keep_case(m[&lword[..]], word)));
-    }
-
-    (map, out)
-}
+type Trace<'a> = Option<&'a mut Vec<Match>>;
 
-fn replace_exceptions_back(input: &str, map: Vec<(String, String)>) -> String {
-    let mut out = input.to_string();
-    for (key, value) in map {
-        out = out.replace(&key, &value);
+// ENDING_WORD only wraps the word ending that fired, which is the one to report
+fn fired_rule(pair: &Pair<Rule>) -> Rule {
+    match pair.as_rule() {
+        Rule::ENDING_WORD => pair.clone().into_inner().last()
+            .map_or(Rule::ENDING_WORD, |p| p.as_rule()),
+        rule => rule,
     }
-
-    out
 }
 
-fn circumflex<'a>(vowel: &'a str) -> &'a str {
+fn circumflex(vowel: &str) -> &str {
     match vowel {
         "a" => "â",
         "e" => "ê",
@@ -115,7 +146,7 @@ fn circumflex<'a>(vowel: &'a str) -> &'a str {
     }
 }
 
-fn tilde<'a>(vowel: &'a str) -> &'a str {
+fn tilde(vowel: &str) -> &str {
     match vowel {
         "a" => "á",
         "e" => "é",
@@ -135,18 +166,160 @@ fn tilde<'a>(vowel: &'a str) -> &'a str {
 #[grammar = "andaluh.pest"]
 pub struct AndaluhParser;
 
-fn keep_case(input: &str, case: &str) -> String {
-    chars!(input).zip(chars!(case))
-        .map(|(i, c)| {
-            match c.chars().next().unwrap_or('x').is_uppercase() {
-                true => i.to_uppercase().to_string(),
-                false => i.to_lowercase().to_string(),
+/// Andaluz spelling proposal
+///
+/// EPA is the only one for now. The builder of `Transliterator` already takes
+/// it, so adding another proposal won't break the code that picks one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Proposal {
+    /// Er Prinzipito Andaluh
+    #[default]
+    Epa,
+}
+
+/// Grapheme used to write the voiceless alveolar fricative (VAF)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Vaf {
+    /// `ç`, the EPA proposal
+    #[default]
+    Cedilla,
+    /// `z`, for ceceo speakers
+    Z,
+    /// `s`, for seseo speakers
+    S,
+    /// `h`, for heheo speakers
+    H,
+}
+
+impl Vaf {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Vaf::Cedilla => "ç",
+            Vaf::Z => "z",
+            Vaf::S => "s",
+            Vaf::H => "h",
+        }
+    }
+}
+
+impl FromStr for Vaf {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Vaf, Error> {
+        match &s.to_lowercase()[..] {
+            "ç" | "cedilla" => Ok(Vaf::Cedilla),
+            "z" => Ok(Vaf::Z),
+            "s" => Ok(Vaf::S),
+            "h" => Ok(Vaf::H),
+            _ => Err(Error::InvalidConfig(format!("unknown VAF {:?}", s))),
+        }
+    }
+}
+
+/// Grapheme used to write the voiceless velar fricative (VVF)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Vvf {
+    /// `h`, the EPA proposal
+    #[default]
+    H,
+    /// `j`
+    J,
+}
+
+impl Vvf {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Vvf::H => "h",
+            Vvf::J => "j",
+        }
+    }
+}
+
+impl FromStr for Vvf {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Vvf, Error> {
+        match &s.to_lowercase()[..] {
+            "h" => Ok(Vvf::H),
+            "j" => Ok(Vvf::J),
+            _ => Err(Error::InvalidConfig(format!("unknown VVF {:?}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EpaOptions {
+    pub vaf: Vaf,
+    pub vvf: Vvf,
+    /// Keep URLs, e-mail addresses, @mentions and #hashtags as they are
+    pub escape_links: bool,
+    /// With `escape_links`, transliterate the body of the #hashtags anyway
+    pub transliterate_hashtags: bool,
+    /// Keep as they are the words the grammar can't parse instead of failing
+    pub lenient: bool,
+    /// Words the rules don't touch, written at the end with their replacement
+    pub exceptions: Dictionary,
+    /// Words each rule replaces before looking at its own exception table
+    pub rule_exceptions: Exceptions,
+    /// Unicode form of the output, the input is always composed first
+    pub normalization: Normalization,
+}
+
+/// Text the lenient mode left untouched
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    /// Byte range in the input
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+// The rules always work with EPA's ç and h, so every table keyed with them
+// keeps matching, and the chosen VAF and VVF graphemes are only written at the
+// end over the ones the rules wrote, along with the words of the user
+// dictionary every rule before has kept.
+fn vaf_output(input: &str, options: &EpaOptions, trace: Trace, marks: &Marks) -> Result<String, Error> {
+    let vaf = options.vaf;
+    let write_vaf = |text: &str| {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                'ç' => out.push_str(vaf.as_str()),
+                'Ç' => out.push_str(&vaf.as_str().to_uppercase()),
+                _ => out.push(c),
             }
-        }).collect::<String>()
+        }
+        out
+    };
+
+    let spans = protect::spans_with(input, options, |word| options.exceptions.get(word).map(write_vaf));
+    protect::transliterate_at(spans, trace, |text, offset, trace| Ok(marks.write(text, offset, options, trace)))
+}
+
+// Runs a rule on its own over text that may have the VVF of the options
+// already, writing it over the h the rule keeps or writes
+fn with_vvf(input: String, marks: Marks, options: &EpaOptions, rule: RuleFn) -> Result<String, Error> {
+    if options.vvf == Vvf::H {
+        return rule(&input, options, None);
+    }
+
+    let mut matches = vec![];
+    let output = rule(&input, options, Some(&mut matches))?;
+    let options = EpaOptions { vaf: Vaf::Cedilla, ..options.clone() };
+
+    Ok(marks.follow(&input, &matches).write(&output, 0, &options, None))
+}
+
+fn keep_case(input: &str, case: &str) -> String {
+    Case::of(case).apply(input)
 }
 
 pub fn h_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::h, &input, Some(&defs::H_RULES_EXCEPT),
+    h_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn h_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::h, input, options, trace, Some(&defs::H_RULES_EXCEPT),
         Rule::initial_h | Rule::inner_h => |pair: Pair<Rule>| {
             let s = pair.as_str();
             let h = slice!(s, 0, 1);
@@ -154,15 +327,19 @@ pub fn h_rule(input: &str) -> Result<String, Error> {
             keep_case(&next, &h)
         },
         Rule::hue => |pair: Pair<Rule>| {
-            keep_case("güe", &pair.as_str())
+            keep_case("güe", pair.as_str())
         },
         Rule::hua => |pair: Pair<Rule>| {
-            keep_case("gua", &pair.as_str())
+            keep_case("gua", pair.as_str())
         })
 }
 
 pub fn x_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::x, input,
+    x_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn x_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::x, input, options, trace,
         Rule::initial_x => |pair: Pair<Rule>| {
             let s = &pair.as_str();
             let next = &s[1..];
@@ -178,19 +355,33 @@ pub fn x_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn ch_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::ch, input,
+    ch_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn ch_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::ch, input, options, trace,
         Rule::CH => |pair: Pair<Rule>| {
             keep_case("x", pair.as_str())
         })
 }
 
 pub fn gj_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::gj, input, Some(&defs::GJ_RULES_EXCEPT),
+    gj_rule_with(input, &EpaOptions::default())
+}
+
+pub fn gj_rule_with(input: &str, options: &EpaOptions) -> Result<String, Error> {
+    with_vvf(input.to_string(), Marks::default(), options, gj_rule_traced)
+}
+
+fn gj_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::gj, input, options, trace, Some(&defs::GJ_RULES_EXCEPT),
         Rule::BUE1 => |pair: Pair<Rule>| {
+            // There's no sp group at the start of the input
             let groups: Vec<Pair<Rule>> = pair.into_inner().collect();
-            let s = groups[0].as_str().to_string();
-            let b = groups[1].as_str().to_string();
-            let next = groups[2].as_str().to_string();
+            let n = groups.len();
+            let s = groups[..n - 2].iter().map(|g| g.as_str()).collect::<String>();
+            let b = groups[n - 2].as_str().to_string();
+            let next = groups[n - 1].as_str().to_string();
             s + &keep_case("g", &b) + &next
         },
         Rule::BUE => |pair: Pair<Rule>| {
@@ -222,7 +413,11 @@ pub fn gj_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn v_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::v, input, Some(&defs::V_RULES_EXCEPT),
+    v_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn v_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::v, input, options, trace, Some(&defs::V_RULES_EXCEPT),
         Rule::NV => |pair: Pair<Rule>| {
                     let s = pair.as_str();
                     keep_case("mb", s)
@@ -234,7 +429,11 @@ pub fn v_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn ll_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::ll, input, Some(&defs::LL_RULES_EXCEPT),
+    ll_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn ll_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::ll, input, options, trace, Some(&defs::LL_RULES_EXCEPT),
         Rule::LL => |pair: Pair<Rule>| {
             let s = pair.as_str();
             keep_case("y", s)
@@ -242,7 +441,11 @@ pub fn ll_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn l_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::l, input,
+    l_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn l_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::l, input, options, trace,
         Rule::L => |pair: Pair<Rule>| {
             let s = pair.as_str();
             keep_case("r", s)
@@ -250,7 +453,11 @@ pub fn l_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn psico_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::psico, input,
+    psico_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn psico_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::psico, input, options, trace,
         Rule::PSIC | Rule::PSEUD => |pair: Pair<Rule>| {
             let s = pair.as_str();
             slice!(s, 1)
@@ -258,7 +465,11 @@ pub fn psico_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn vaf_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::vaf, input,
+    vaf_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn vaf_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::vaf, input, options, trace,
         Rule::ZSv | Rule::Cv => |pair: Pair<Rule>| {
             let s = pair.as_str();
             let next = slice!(s, 1);
@@ -267,13 +478,23 @@ pub fn vaf_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn word_ending_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::word_ending, input,
+    word_ending_rule_with(input, &EpaOptions::default())
+}
+
+pub fn word_ending_rule_with(input: &str, options: &EpaOptions) -> Result<String, Error> {
+    let (text, marks) = marks::read_vvf(input, options);
+    with_vvf(text, marks, options, word_ending_rule_traced)
+}
+
+fn word_ending_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::word_ending, input, options, trace,
         Rule::ENDING_WORD => |pair: Pair<Rule>| {
             let mut output: Vec<String> = vec![];
             let word = pair.as_str();
             let word_lowercase = word.to_string().to_lowercase();
             let lword = &word_lowercase[..];
             let n = len!(word);
+            let last_stressed = syllables::stress(word) == Some(Stress::Aguda);
             for pair in pair.into_inner() {
                 let chunk = match pair.as_rule() {
                     Rule::ENDING_D => {
@@ -286,8 +507,7 @@ pub fn word_ending_rule(input: &str) -> Result<String, Error> {
                         let vowel = slice!(word, n - 2, n - 1);
                         let d = slice!(word, n - 1);
 
-                        let pl = prefix.to_lowercase();
-                        if ["á", "é", "í", "ó", "ú"].iter().any(|x| pl.contains(x)) {
+                        if !last_stressed {
                             return prefix + defs::WORD_ENDING_D_UNSTRESS[&vowel[..]];
                         }
 
@@ -330,8 +550,7 @@ pub fn word_ending_rule(input: &str) -> Result<String, Error> {
                         let vowel = slice!(word, n - 2, n - 1);
                         let c = slice!(word, n - 1);
 
-                        let pl = prefix.to_lowercase();
-                        if ["á", "é", "í", "ó", "ú"].iter().any(|x| pl.contains(x)) {
+                        if !last_stressed {
                             return prefix + defs::WORD_ENDING_CONS[&vowel[..]];
                         }
 
@@ -341,8 +560,7 @@ pub fn word_ending_rule(input: &str) -> Result<String, Error> {
                         let prefix = slice!(word, 0, n - 3);
                         let e = slice!(word, n - 3, n - 2);
 
-                        let pl = prefix.to_lowercase();
-                        if ["á", "é", "í", "ó", "ú"].iter().any(|x| pl.contains(x)) {
+                        if !last_stressed {
                             return prefix + circumflex(&e)
                         }
 
@@ -370,8 +588,8 @@ pub fn word_ending_rule(input: &str) -> Result<String, Error> {
                             suffix += &last;
                         }
 
-                        let pl = prefix.to_lowercase();
-                        if ["á", "é", "í", "ó", "ú"].iter().any(|x| pl.contains(x)) {
+                        // Only when the stress is on the vowel before the d
+                        if syllables::stress(word) != Some(Stress::Llana) {
                             return word.to_string();
                         }
 
@@ -413,7 +631,11 @@ pub fn word_ending_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn digraph_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::digraph, input,
+    digraph_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn digraph_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::digraph, input, options, trace,
         Rule::DIGRAPH_EXP_LSTRST => |pair: Pair<Rule>| {
             let groups: Vec<Pair<Rule>> = pair.into_inner().collect();
             let vowel = groups[0].as_str().to_string();
@@ -470,19 +692,26 @@ pub fn digraph_rule(input: &str) -> Result<String, Error> {
 }
 
 pub fn exception_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::exception, input,
-        Rule::word => |pair: Pair<Rule>| {
-            let s = pair.as_str();
-            let out = defs::ENDING_RULES_EXCEPTION
-                .get(&s.to_lowercase()[..])
-                .unwrap_or(&s);
+    exception_rule_with(input, &EpaOptions::default())
+}
 
-            keep_case(out, s)
-        })
+pub fn exception_rule_with(input: &str, options: &EpaOptions) -> Result<String, Error> {
+    let (text, marks) = marks::read_vvf(input, options);
+    with_vvf(text, marks, options, exception_rule_traced)
+}
+
+fn exception_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    let spans = protect::spans(input, options, Some(&defs::ENDING_RULES_EXCEPTION));
+
+    protect::transliterate(spans, trace, |text, _| Ok(text.to_string()))
 }
 
 pub fn word_interaction_rule(input: &str) -> Result<String, Error> {
-    rule!(Rule::word_interaction, input,
+    word_interaction_rule_traced(input, &EpaOptions::default(), None)
+}
+
+fn word_interaction_rule_traced(input: &str, options: &EpaOptions, trace: Trace) -> Result<String, Error> {
+    rule!(Rule::word_interaction, input, options, trace,
         Rule::ENDING_L => |pair: Pair<Rule>| {
             let groups: Vec<Pair<Rule>> = pair.into_inner().collect();
             let prefix = groups[0].as_str().to_string();
@@ -494,37 +723,166 @@ pub fn word_interaction_rule(input: &str) -> Result<String, Error> {
         })
 }
 
+type RuleFn = fn(&str, &EpaOptions, Trace) -> Result<String, Error>;
+
+// The rules of EPA, with whether they only look at a word at a time, which
+// the word interaction rule doesn't, as it looks at the next word
+const EPA_RULES: [(&str, RuleFn, bool); 13] = [
+    ("h_rule", h_rule_traced, true),
+    ("x_rule", x_rule_traced, true),
+    ("ch_rule", ch_rule_traced, true),
+    ("gj_rule", gj_rule_traced, true),
+    ("v_rule", v_rule_traced, true),
+    ("ll_rule", ll_rule_traced, true),
+    ("l_rule", l_rule_traced, true),
+    ("psico_rule", psico_rule_traced, true),
+    ("vaf_rule", vaf_rule_traced, true),
+    ("word_ending_rule", word_ending_rule_traced, true),
+    ("digraph_rule", digraph_rule_traced, true),
+    ("exception_rule", exception_rule_traced, true),
+    ("word_interaction_rule", word_interaction_rule_traced, false),
+];
+
+lazy_static! {
+    static ref EPA: pipeline::Pipeline = pipeline::Pipeline::epa();
+}
+
 pub fn epa(input: &str) -> Result<String, Error> {
-    // TODO: escape links
-    let rules = [
-        h_rule,
-        x_rule,
-        ch_rule,
-        gj_rule,
-        v_rule,
-        ll_rule,
-        l_rule,
-        psico_rule,
-        vaf_rule,
-        word_ending_rule,
-        digraph_rule,
-        exception_rule,
-        word_interaction_rule,
-    ];
+    epa_with(input, &EpaOptions::default())
+}
 
-    let mut output = input.to_string();
-    for r in rules.iter() {
-        let out = r(&output)?;
-        output = out.to_string();
-    }
+pub fn epa_with(input: &str, options: &EpaOptions) -> Result<String, Error> {
+    EPA.transliterate(input, options)
+}
+
+/// Runs `epa_with` in the lenient mode, returning the text left untouched
+pub fn epa_lenient(input: &str, options: &EpaOptions) -> Result<(String, Vec<Warning>), Error> {
+    EPA.transliterate_lenient(input, options)
+}
+
+/// Runs `epa_with` over the text of reader, writing it to writer as it goes
+pub fn epa_stream<R: BufRead, W: Write>(reader: R, writer: W, options: &EpaOptions) -> Result<(), Error> {
+    stream::transliterate(&EPA, options, reader, writer).map(|_| ())
+}
+
+/// Runs `epa_stream` in the lenient mode, returning the text left untouched
+pub fn epa_stream_lenient<R: BufRead, W: Write>(reader: R, writer: W, options: &EpaOptions)
+    -> Result<Vec<Warning>, Error> {
+    let options = EpaOptions { lenient: true, ..options.clone() };
+    stream::transliterate(&EPA, &options, reader, writer)
+}
+
+/// Runs `epa_with` returning where every piece of the output comes from
+pub fn epa_aligned(input: &str, options: &EpaOptions) -> Result<(String, Vec<Alignment>), Error> {
+    EPA.transliterate_aligned(input, options)
+}
+
+/// Transliterates a single word, with the transformations every rule made
+pub fn transliterate_word(word: &str) -> Result<(String, Vec<Transformation>), Error> {
+    transliterate_word_with(word, &EpaOptions::default())
+}
+
+pub fn transliterate_word_with(word: &str, options: &EpaOptions) -> Result<(String, Vec<Transformation>), Error> {
+    explain::transliterate_word(&EPA, word, options)
+}
+
+/// Transliterates the text of an HTML document, keeping its markup as it is
+pub fn epa_html(input: &str, options: &EpaOptions, html_options: &HtmlOptions) -> Result<String, Error> {
+    EPA.transliterate_html(input, options, html_options)
+}
+
+/// Transliterates the prose of a Markdown document, keeping code, URLs, HTML
+/// blocks and the front matter as they are
+pub fn epa_markdown(input: &str, options: &EpaOptions) -> Result<String, Error> {
+    EPA.transliterate_markdown(input, options)
+}
+
+/// Transliterates the text of the cues of SRT or WebVTT subtitles, keeping
+/// their timings and styling tags as they are
+pub fn epa_subtitles(input: &str, options: &EpaOptions, format: Subtitles) -> Result<String, Error> {
+    EPA.transliterate_subtitles(input, options, format)
+}
 
-    Ok(output)
+/// Transliterates the translations of a gettext PO catalogue, keeping the
+/// placeholders, comments and flags as they are
+pub fn epa_po(input: &str, options: &EpaOptions, po_options: &PoOptions) -> Result<String, Error> {
+    EPA.transliterate_po(input, options, po_options)
+}
+
+/// Transliterates the string values of a JSON or YAML locale file, keeping
+/// the keys, the ICU MessageFormat syntax and the placeholders as they are
+pub fn epa_resource(input: &str, options: &EpaOptions, format: Resource) -> Result<String, Error> {
+    EPA.transliterate_resource(input, options, format)
+}
+
+/// Broad IPA transcription of the pronunciation the EPA spelling writes
+pub fn ipa(input: &str, ipa_options: &IpaOptions) -> Result<String, Error> {
+    ipa_with(input, &EpaOptions::default(), ipa_options)
+}
+
+/// Like `ipa`, with the options of the rules, the graphemes aren't used
+pub fn ipa_with(input: &str, options: &EpaOptions, ipa_options: &IpaOptions) -> Result<String, Error> {
+    EPA.transcribe_ipa(input, options, ipa_options)
+}
+
+/// Runs `epa_with` keeping what every rule did to the text
+pub fn epa_trace(input: &str, options: &EpaOptions) -> Result<Vec<Step>, Error> {
+    EPA.trace(input, options)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Every input of the tests of this module, that the ones of the pipeline
+    // also run word by word
+    pub(crate) const CORPUS: &[&str] = &[
+        "hotel HOTEL zanahoria harina chihUahua cacaHuEte escuchar",
+        "Xilófono axila éxito xenofobia",
+        "Chungo Chachi escuchar",
+        "Guijarrito ABuelo VERGÜENZA BUEN jamón",
+        "envidia valor lleva",
+        "lleva valla",
+        "silbar acolchado",
+        "psicologo pseudoescritor",
+        "Zaragoza solsticio",
+        "Madrid tazas disfraz Colocados tomate tríceps triceps malnacidas",
+        "reloj lápiz caracteres césped ataúd Sáez cuidados ruido válido político-social",
+        "asfixian Conmemorar atlántico abstracto perspectiva aerotransporte translado intersticial \
+         solsticio superstición aislante",
+        "tomate biêmmandao TuRuRú crack",
+        "el transcurso",
+        "Todo Xenomorfo dice: [haber], que el Éxito y el éxtasis asfixian, si no eres un xilófono Chungo.",
+        "Lleva un Guijarrito el ABuelo, ¡Qué Bueno! ¡para la VERGÜENZA!",
+        "VALLA valla, si vas toda de ENVIDIA",
+        "Alrededor de la Alpaca había un ALfabeto ALTIVO de valkirias malnacidas",
+        "En la Zaragoza y el Japón asexual se Sabía SÉriamente sILBAR con el COxis",
+        "Transportandonos a la connotación perspicaz del abstracto solsticio de Alaska, el aislante \
+         plástico adsorvente asfixió al aMnésico pseudoescritor granadino de constituciones, para ConMemorar \
+         broncas adscritas",
+        "En la postmodernidad, el transcurso de los transportes y translados en postoperatorios \
+         transcienden a la postre unas postillas postpalatales apostilladas se transfieren",
+        "Venid todos a correr en anorak de visón a Cádiz con actitud y maldad, para escuchar el tríceps de \
+         Albéniz tocar ápud con virtud de laúd.",
+        "Una comida fabada con fado, y sin descuido será casada y amarrada al acolchado roido.",
+        "Los SABuesos ChiHuaHUA comían cacaHuETes, FramBuESas y Heno, ¡y HABLAN con hálito de ESPANGLISH!",
+        "Zaragoza acceso sed Cielo",
+        "El Barça de Zaragoza",
+        "Jamón gente Algeciras subjefe Gido",
+        "Uir con Gido",
+        "jerôççiquiatría uir",
+        "Jamón de Jerez",
+        "Escribe a info@sevilla.es o a @juan_hidalgo desde https://www.sevilla.es #SemanaSanta",
+        "Ves vestido gin, ¡JAZZ! AND_0",
+        "Uir STOP stop necrópsico",
+        "Ves a @juan en Madrid",
+        "Bueno, el\ttranscurso de la Alpaca\nhabía sido un éxito\r\n\r\nCon el\nsaxofón, ¡Venid!\n",
+        " \n\tamigo\r\n",
+        "Hola\u{0007}, Juan 👨\u{200D}👩 y Xilófono",
+        "Mira https://www.sevilla.es/a\u{200D}b",
+        "Mira @juan\nhola\u{0007}mundo",
+    ];
+
     #[test]
     fn test_h_rule() {
         let input = "hotel HOTEL zanahoria harina chihUahua cacaHuEte escuchar";
@@ -613,6 +971,12 @@ mod tests {
 
         let output = word_ending_rule(input).expect("Wrong parser");
         assert_eq!(output, expected);
+
+        let input = "reloj lápiz caracteres césped ataúd Sáez cuidados ruido válido político-social";
+        let expected = "relôh lápî caracterê céspê ataûh Sáê cuidáô ruío válido político-sociâh";
+
+        let output = word_ending_rule(input).expect("Wrong parser");
+        assert_eq!(output, expected);
     }
 
     #[test]
@@ -692,4 +1056,167 @@ mod tests {
             assert_eq!(output, expected);
         }
     }
+
+    #[test]
+    fn test_epa_vaf() {
+        let input = "Zaragoza acceso sed Cielo";
+        let test_vafs = vec![
+            (Vaf::Cedilla, "Çaragoça âççeço çêh Çielo"),
+            (Vaf::Z, "Zaragoza âzzezo zêh Zielo"),
+            (Vaf::S, "Saragosa âsseso sêh Sielo"),
+            (Vaf::H, "Haragoha âhheho hêh Hielo"),
+        ];
+
+        for (vaf, expected) in test_vafs {
+            let options = EpaOptions { vaf, ..Default::default() };
+            let output = epa_with(input, &options).expect("Wrong parser");
+            assert_eq!(output, expected);
+        }
+
+        // Only the ç the rules wrote are a VAF to write, not the ones of the input
+        let options = EpaOptions { vaf: Vaf::Z, ..Default::default() };
+        let output = epa_with("El Barça de Zaragoza", &options).expect("Wrong parser");
+        assert_eq!(output, "Er Barça de Zaragoza");
+    }
+
+    #[test]
+    fn test_epa_vvf() {
+        let input = "Jamón gente Algeciras subjefe Gido";
+        let test_vvfs = vec![
+            (Vvf::H, "Hamón hente Arheçirâ çûhhefe Hido"),
+            (Vvf::J, "Jamón jente Arjeçirâ çûjjefe Jido"),
+        ];
+
+        for (vvf, expected) in test_vvfs {
+            let options = EpaOptions { vvf, ..Default::default() };
+            let output = epa_with(input, &options).expect("Wrong parser");
+            assert_eq!(output, expected);
+        }
+
+        // The aspirated h of the exception tables aren't the VVF
+        let options = EpaOptions { vvf: Vvf::J, ..Default::default() };
+        let output = epa_with("Uir con Gido", &options).expect("Wrong parser");
+        assert_eq!(output, "Huîh con Jido");
+
+        let output = exception_rule_with("jerôççiquiatría uir", &options).expect("Wrong parser");
+        assert_eq!(output, "jeroçiquiatría uir");
+        // Neither are the aspirated endings of the words with one
+        let input = "el jamón de Jerez general Jesús Gil juventud jamás";
+        let output = epa_with(input, &options).expect("Wrong parser");
+        assert_eq!(output, "er jamón de Jerêh jenerâh Jeçûh Jîh jubentûh jamâh");
+
+        let output = gj_rule_with("Jamón de Jerez", &options).expect("Wrong parser");
+        assert_eq!(output, "Jamón de Jerez");
+    }
+
+    #[test]
+    fn test_epa_escape_links() {
+        let input = "Escribe a info@sevilla.es o a @juan_hidalgo desde https://www.sevilla.es #SemanaSanta";
+        let test_options = vec![
+            (false, "Êccribe a info@sevilla.es o a @juan_hidalgo dêdde https://www.sevilla.es #SemanaSanta"),
+            (true, "Êccribe a info@sevilla.es o a @juan_hidalgo dêdde https://www.sevilla.es #ÇemanaÇanta"),
+        ];
+
+        for (transliterate_hashtags, expected) in test_options {
+            let options = EpaOptions {
+                escape_links: true,
+                transliterate_hashtags,
+                ..Default::default()
+            };
+            let output = epa_with(input, &options).expect("Wrong parser");
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_exceptions_whole_words() {
+        let input = "Ves vestido gin, ¡JAZZ! AND_0";
+        let expected = "Bêh bestido yin, ¡YÂH! AND_0";
+
+        let output = v_rule(input).and_then(|o| gj_rule(&o)).expect("Wrong parser");
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_exceptions_keep_case() {
+        let input = "Uir STOP stop necrópsico";
+        let expected = "Huîh ÊTTÔH êttôh necróççico";
+
+        let output = epa(input).expect("Wrong parser");
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_epa_trace() {
+        let options = EpaOptions { escape_links: true, ..Default::default() };
+        let steps = epa_trace("Ves a @juan en Madrid", &options).expect("Wrong parser");
+        let output = epa_with("Ves a @juan en Madrid", &options).expect("Wrong parser");
+
+        assert_eq!(steps.len(), 14);
+        assert_eq!(steps[13].output, output);
+
+        assert_eq!(steps[4].name, "v_rule");
+        assert_eq!(steps[4].matches, vec![
+            Match { rule: None, span: 0..3, output: "Bêh".to_string() },
+        ]);
+
+        assert_eq!(steps[9].name, "word_ending_rule");
+        assert_eq!(steps[9].matches, vec![
+            Match { rule: Some(Rule::ENDING_D), span: 16..22, output: "Madrîh".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_epa_paragraphs() {
+        let input = "Bueno, el\ttranscurso de la Alpaca\nhabía sido un éxito\r\n\r\nCon el\nsaxofón, ¡Venid!\n";
+        let expected = "Gueno, er\ttrâccurço de la Arpaca\nabía çido un éççito\r\n\r\nCon er\nçâççofón, ¡Benîh!\n";
+
+        let output = epa(input).expect("Wrong parser");
+        assert_eq!(output, expected);
+
+        let rules = [h_rule, x_rule, ch_rule, gj_rule, v_rule, ll_rule, l_rule, psico_rule,
+                     vaf_rule, word_ending_rule, digraph_rule, exception_rule, word_interaction_rule];
+        for r in rules.iter() {
+            let output = r(" \n\tamigo\r\n").expect("Wrong parser");
+            assert_eq!(output, " \n\tamigo\r\n");
+        }
+    }
+
+    #[test]
+    fn test_epa_lenient() {
+        let input = "Hola\u{0007}, Juan 👨\u{200D}👩 y Xilófono";
+        let expected = "Hola\u{0007}, Huan 👨\u{200D}👩 y Çilófono";
+
+        assert!(epa(input).is_err());
+
+        let (output, warnings) = epa_lenient(input, &EpaOptions::default()).expect("Wrong parser");
+        assert_eq!(output, expected);
+        assert_eq!(warnings, vec![
+            Warning { span: 0..6, message: "unsupported character U+0007, text kept as is".to_string() },
+            Warning { span: 12..23, message: "unsupported character U+200D, text kept as is".to_string() },
+        ]);
+
+        // An escaped link is kept anyway, without a warning
+        let options = EpaOptions { escape_links: true, ..Default::default() };
+        let (output, warnings) = epa_lenient("Mira https://www.sevilla.es/a\u{200D}b", &options)
+            .expect("Wrong parser");
+        assert_eq!(output, "Mira https://www.sevilla.es/a\u{200D}b");
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_grammar_error() {
+        let options = EpaOptions { escape_links: true, ..Default::default() };
+        let error = epa_with("Mira @juan\nhola\u{0007}mundo", &options).expect_err("Wrong parser");
+
+        match error {
+            Error::Grammar { rule, offset, line, column, snippet } => {
+                assert_eq!(rule, Rule::h);
+                assert_eq!(offset, 15);
+                assert_eq!((line, column), (2, 5));
+                assert_eq!(snippet, "\u{0007}mundo");
+            },
+            _ => panic!("Wrong error {:?}", error),
+        }
+    }
 }
@@ -0,0 +1,419 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use andaluh::{Error, HtmlOptions, IpaOptions, PoOptions, Resource, Subtitles, Transliterator};
+
+const USAGE: &str = "Usage: andaluh [OPTIONS] [FILE]...
+
+Transliterates español (spanish) spelling to andaluz (EPA proposal), reading
+the files or the standard input when there's none or it's -.
+
+Options:
+    --vaf <ç|z|s|h>             Grapheme for the voiceless alveolar fricative
+    --vvf <h|j>                 Grapheme for the voiceless velar fricative
+    --escape-links              Keep URLs, e-mails, @mentions and #hashtags
+    --transliterate-hashtags    Transliterate the body of the #hashtags anyway
+    --lenient                   Keep the words that can't be parsed, listing
+                                them on the standard error for plain text
+    --normalization <nfc|nfd>   Unicode form of the output, NFC by default
+    --rules <RULE,...>          Only run these rules of the pipeline
+    --exceptions <FILE>         Add the exceptions of the rules in a TSV, TOML
+                                or JSON file, the last ones win
+    --format <FORMAT>           Format of the files: text, html, markdown, srt,
+                                vtt, po, json or yaml, only their prose is
+                                transliterated
+    --html-attributes           Transliterate the title and alt attributes too
+    --fuzzy                     Mark the transliterated PO entries as fuzzy
+    --trace                     Show the output of every rule
+    --ipa                       Write a broad IPA transcription instead
+    --sibilant <seseo|ceceo>    Sound of ç in the IPA, seseo by default
+    --vowels <western|eastern>  Vowels before a lost consonant in the IPA,
+                                western (closed) by default
+    -o, --output <FILE>         Write to a file instead of the standard output,
+                                that is -, where json and yaml files go to a
+                                sibling like es-AND.json by default
+    -i, --in-place              Write every file back
+    -h, --help                  Show this help
+    -V, --version               Show the version";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    vaf: Option<String>,
+    vvf: Option<String>,
+    escape_links: bool,
+    transliterate_hashtags: bool,
+    lenient: bool,
+    normalization: Option<String>,
+    rules: Option<Vec<String>>,
+    exceptions: Vec<PathBuf>,
+    format: Option<String>,
+    html_attributes: bool,
+    fuzzy: bool,
+    trace: bool,
+    ipa: bool,
+    sibilant: Option<String>,
+    vowels: Option<String>,
+    output: Option<PathBuf>,
+    in_place: bool,
+    files: Vec<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run(Box<Args>),
+    Help,
+    Version,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut argv: I) -> Result<Command, String> {
+    let mut args = Args::default();
+
+    while let Some(arg) = argv.next() {
+        // --option=value is the same as --option value
+        let (arg, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg, None),
+        };
+        let mut value = |name: &str| {
+            inline.clone().or_else(|| argv.next())
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+
+        match &arg[..] {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--vaf" => args.vaf = Some(value("--vaf")?),
+            "--vvf" => args.vvf = Some(value("--vvf")?),
+            "--escape-links" => args.escape_links = true,
+            "--transliterate-hashtags" => args.transliterate_hashtags = true,
+            "--lenient" => args.lenient = true,
+            "--normalization" => args.normalization = Some(value("--normalization")?),
+            "--rules" => {
+                let rules = value("--rules")?;
+                args.rules = Some(rules.split(',').map(|r| r.trim().to_string()).collect());
+            },
+            "--exceptions" => args.exceptions.push(PathBuf::from(value("--exceptions")?)),
+            "--format" => args.format = Some(value("--format")?),
+            "--html-attributes" => args.html_attributes = true,
+            "--fuzzy" => args.fuzzy = true,
+            "--trace" => args.trace = true,
+            "--ipa" => args.ipa = true,
+            "--sibilant" => args.sibilant = Some(value("--sibilant")?),
+            "--vowels" => args.vowels = Some(value("--vowels")?),
+            "-o" | "--output" => args.output = Some(PathBuf::from(value("--output")?)),
+            "-i" | "--in-place" => args.in_place = true,
+            "-" => args.files.push(PathBuf::from(arg)),
+            a if a.starts_with('-') => return Err(format!("unknown option {}", a)),
+            _ => args.files.push(PathBuf::from(arg)),
+        }
+    }
+
+    if args.in_place && (args.files.is_empty() || args.files.iter().any(|f| f == Path::new("-"))) {
+        return Err("--in-place needs files".to_string());
+    }
+    if args.trace && args.ipa {
+        return Err("--trace and --ipa can't be used together".to_string());
+    }
+    if args.in_place && args.output.is_some() {
+        return Err("--in-place and --output can't be used together".to_string());
+    }
+    // The options of a mode do nothing without it
+    let format = args.format.as_deref().unwrap_or("text").to_lowercase();
+    if !args.ipa && (args.sibilant.is_some() || args.vowels.is_some()) {
+        return Err("--sibilant and --vowels need --ipa".to_string());
+    }
+    if args.fuzzy && format != "po" && format != "pot" {
+        return Err("--fuzzy needs --format po".to_string());
+    }
+    if args.html_attributes && format != "html" {
+        return Err("--html-attributes needs --format html".to_string());
+    }
+
+    Ok(Command::Run(Box::new(args)))
+}
+
+// What is written for every file
+enum Mode {
+    Epa,
+    Trace,
+    Ipa(IpaOptions),
+    Html(HtmlOptions),
+    Markdown,
+    Subtitles(Subtitles),
+    Po(PoOptions),
+    Resource(Resource),
+}
+
+fn mode(args: &Args) -> Result<Mode, Error> {
+    let format = args.format.as_deref().unwrap_or("text").to_lowercase();
+    if format != "text" && (args.trace || args.ipa) {
+        return Err(Error::InvalidConfig("--trace and --ipa only read plain text".to_string()));
+    }
+    match &format[..] {
+        "text" => {},
+        "html" => return Ok(Mode::Html(HtmlOptions { attributes: args.html_attributes })),
+        "markdown" | "md" => return Ok(Mode::Markdown),
+        "srt" => return Ok(Mode::Subtitles(Subtitles::Srt)),
+        "vtt" | "webvtt" => return Ok(Mode::Subtitles(Subtitles::WebVtt)),
+        "po" | "pot" => return Ok(Mode::Po(PoOptions { fuzzy: args.fuzzy })),
+        "json" => return Ok(Mode::Resource(Resource::Json)),
+        "yaml" | "yml" => return Ok(Mode::Resource(Resource::Yaml)),
+        _ => return Err(Error::InvalidConfig(format!("unknown format {:?}", format))),
+    }
+
+    if args.trace {
+        return Ok(Mode::Trace);
+    }
+    if !args.ipa {
+        return Ok(Mode::Epa);
+    }
+
+    let mut options = IpaOptions::default();
+    if let Some(sibilant) = &args.sibilant {
+        options.sibilant = sibilant.parse()?;
+    }
+    if let Some(vowels) = &args.vowels {
+        options.vowels = vowels.parse()?;
+    }
+
+    Ok(Mode::Ipa(options))
+}
+
+fn build_transliterator(args: &Args) -> Result<Transliterator, Error> {
+    let mut builder = Transliterator::builder()
+        .escape_links(args.escape_links)
+        .transliterate_hashtags(args.transliterate_hashtags)
+        .lenient(args.lenient);
+
+    if let Some(vaf) = &args.vaf {
+        builder = builder.vaf(vaf.parse()?);
+    }
+    if let Some(vvf) = &args.vvf {
+        builder = builder.vvf(vvf.parse()?);
+    }
+    if let Some(normalization) = &args.normalization {
+        builder = builder.normalization(normalization.parse()?);
+    }
+    if let Some(rules) = &args.rules {
+        builder = builder.rules(rules);
+    }
+    for path in args.exceptions.iter() {
+        builder = builder.exceptions_file(path);
+    }
+
+    builder.build()
+}
+
+fn write_trace<W: Write>(transliterator: &Transliterator, input: &str, mut writer: W) -> Result<(), Error> {
+    for step in transliterator.trace(input)? {
+        writeln!(writer, "[{}]", step.name)?;
+        write!(writer, "{}", step.output)?;
+        if !step.output.ends_with('\n') {
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn transliterate_file<W: Write>(transliterator: &Transliterator, mode: &Mode, path: &Path, mut writer: W)
+    -> Result<(), Error> {
+    let reader: Box<dyn io::Read> = match path.to_str() {
+        Some("-") => Box::new(io::stdin()),
+        _ => Box::new(File::open(path)?),
+    };
+
+    match mode {
+        Mode::Epa if transliterator.options().lenient => {
+            for warning in transliterator.transliterate_stream_lenient(BufReader::new(reader), writer)? {
+                eprintln!("andaluh: {}: bytes {}..{}: {}",
+                          path.display(), warning.span.start, warning.span.end, warning.message);
+            }
+            Ok(())
+        },
+        Mode::Epa => transliterator.transliterate_stream(BufReader::new(reader), writer),
+        Mode::Trace => {
+            let input = io::read_to_string(reader)?;
+            write_trace(transliterator, &input, writer)
+        },
+        Mode::Ipa(options) => {
+            let input = io::read_to_string(reader)?;
+            Ok(writer.write_all(transliterator.transcribe_ipa(&input, options)?.as_bytes())?)
+        },
+        Mode::Html(options) => {
+            let input = io::read_to_string(reader)?;
+            Ok(writer.write_all(transliterator.transliterate_html(&input, options)?.as_bytes())?)
+        },
+        Mode::Markdown => {
+            let input = io::read_to_string(reader)?;
+            Ok(writer.write_all(transliterator.transliterate_markdown(&input)?.as_bytes())?)
+        },
+        Mode::Subtitles(format) => {
+            let input = io::read_to_string(reader)?;
+            Ok(writer.write_all(transliterator.transliterate_subtitles(&input, *format)?.as_bytes())?)
+        },
+        Mode::Po(options) => {
+            let input = io::read_to_string(reader)?;
+            Ok(writer.write_all(transliterator.transliterate_po(&input, options)?.as_bytes())?)
+        },
+        Mode::Resource(format) => {
+            let input = io::read_to_string(reader)?;
+            Ok(writer.write_all(transliterator.transliterate_resource(&input, *format)?.as_bytes())?)
+        },
+    }
+}
+
+// Writes the output of a file next to target and then moves it over target,
+// so an error never leaves it half written
+fn transliterate_to(transliterator: &Transliterator, mode: &Mode, path: &Path, target: &Path) -> Result<(), Error> {
+    let mut tmp = target.as_os_str().to_owned();
+    tmp.push(".andaluh");
+    let tmp = PathBuf::from(tmp);
+
+    let result = File::create(&tmp).map_err(Error::from).and_then(|f| {
+        f.set_permissions(fs::metadata(path)?.permissions())?;
+        let mut writer = BufWriter::new(f);
+        transliterate_file(transliterator, mode, path, &mut writer)?;
+        Ok(writer.flush()?)
+    });
+    match result {
+        Ok(()) => Ok(fs::rename(&tmp, target)?),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        },
+    }
+}
+
+// File next to a locale file for its transliteration, like es-AND.json for
+// es.json
+fn sibling(path: &Path) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push("-AND");
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+
+    path.with_file_name(name)
+}
+
+fn run(mut args: Args) -> Result<(), Error> {
+    let transliterator = build_transliterator(&args)?;
+    let mode = mode(&args)?;
+    if args.files.is_empty() {
+        args.files.push(PathBuf::from("-"));
+    }
+
+    if args.in_place {
+        for path in args.files.iter() {
+            transliterate_to(&transliterator, &mode, path, path)?;
+        }
+        return Ok(());
+    }
+    if matches!(mode, Mode::Resource(_)) && args.output.is_none() && args.files.iter().all(|f| f != Path::new("-")) {
+        for path in args.files.iter() {
+            transliterate_to(&transliterator, &mode, path, &sibling(path))?;
+        }
+        return Ok(());
+    }
+
+    // -o - is the standard output, like - is the standard input
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(path) if path != Path::new("-") => Box::new(BufWriter::new(File::create(path)?)),
+        _ => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    for path in args.files.iter() {
+        transliterate_file(&transliterator, &mode, path, &mut writer)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Command::Run(args)) => args,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Ok(Command::Version) => {
+            println!("andaluh {}", env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        Err(message) => {
+            eprintln!("andaluh: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        },
+    };
+
+    if let Err(e) = run(*args) {
+        eprintln!("andaluh: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let expected = Args {
+            vaf: Some("s".to_string()),
+            vvf: Some("j".to_string()),
+            escape_links: true,
+            rules: Some(vec!["h_rule".to_string(), "v_rule".to_string()]),
+            exceptions: vec![PathBuf::from("a.toml"), PathBuf::from("b.tsv")],
+            trace: true,
+            files: vec![PathBuf::from("a.txt"), PathBuf::from("-")],
+            ..Default::default()
+        };
+        let args = ["--vaf", "s", "--vvf=j", "--escape-links", "--rules", "h_rule, v_rule",
+                    "--exceptions", "a.toml", "--exceptions=b.tsv", "--trace", "a.txt", "-"];
+        assert_eq!(parse(&args), Ok(Command::Run(Box::new(expected))));
+
+        assert_eq!(parse(&["a.txt", "--help"]), Ok(Command::Help));
+        assert!(parse(&["--vaf"]).is_err());
+        assert!(parse(&["--in-place"]).is_err());
+        assert!(parse(&["--in-place", "-o", "b.txt", "a.txt"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--trace", "--ipa"]).is_err());
+        assert!(parse(&["--sibilant", "ceceo"]).is_err());
+        assert!(parse(&["--vowels", "eastern", "--format", "html"]).is_err());
+        assert!(parse(&["--fuzzy", "--format", "html"]).is_err());
+        assert!(parse(&["--html-attributes"]).is_err());
+        assert!(parse(&["--ipa", "--sibilant", "ceceo"]).is_ok());
+        assert!(parse(&["--html-attributes", "--format=HTML", "--fuzzy", "--format", "pot"]).is_err());
+        assert!(parse(&["--fuzzy", "--format", "POT"]).is_ok());
+    }
+
+    #[test]
+    fn test_run() {
+        let args = Args { rules: Some(vec!["h_rule".to_string(), "x_rule".to_string()]), ..Default::default() };
+        let transliterator = build_transliterator(&args).expect("Wrong configuration");
+
+        let mut output = vec![];
+        write_trace(&transliterator, "Hola Xavi", &mut output).expect("Wrong parser");
+        assert_eq!(String::from_utf8(output).unwrap(), "[h_rule]\nOla Xavi\n[x_rule]\nOla Çavi\n[vaf_output]\nOla Çavi\n");
+
+        assert_eq!(sibling(Path::new("locales/es.json")), PathBuf::from("locales/es-AND.json"));
+        assert_eq!(sibling(Path::new("es")), PathBuf::from("es-AND"));
+
+        let args = Args { vaf: Some("x".to_string()), ..Default::default() };
+        assert!(build_transliterator(&args).is_err());
+
+        let args = Args { ipa: true, vowels: Some("northern".to_string()), ..Default::default() };
+        assert!(mode(&args).is_err());
+        let args = Args { format: Some("html".to_string()), trace: true, ..Default::default() };
+        assert!(mode(&args).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+// Markdown documents, where the rules only see the prose of paragraphs,
+// headings, lists, quotes and tables, link text included, and code, URLs,
+// HTML blocks and the front matter are written back as they are.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::escape;
+use crate::markup::{self, Text, Writer};
+use crate::pipeline::Pipeline;
+use crate::{EpaOptions, Error};
+
+// Tags that start an HTML block, even in the middle of a paragraph
+const HTML_BLOCKS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "details", "dialog", "div", "dl", "fieldset", "figcaption",
+    "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "iframe", "li", "main", "nav",
+    "ol", "p", "pre", "script", "section", "style", "summary", "table", "tbody", "td", "textarea", "tfoot", "th",
+    "thead", "tr", "ul",
+];
+// HTML blocks that end at their end tag instead of at a blank line
+const HTML_RAW: &[&str] = &["pre", "script", "style", "textarea"];
+
+// A block that goes on until a line closes it
+enum Open {
+    // Character and length of the fence
+    Fence(u8, usize),
+    // Text that ends the block, a blank line when empty
+    Html(String),
+}
+
+// Length of the run of c at the start of text
+fn run_of(text: &str, c: u8) -> usize {
+    text.bytes().take_while(|b| *b == c).count()
+}
+
+// Length of the quote markers, the list marker and the heading marker at the
+// start of a line, and whether there was a list marker
+fn prefix(line: &str) -> (usize, bool) {
+    let bytes = line.as_bytes();
+    let blank = |i: usize| bytes.get(i).is_none_or(|b| b.is_ascii_whitespace());
+    let mut i = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let mut list = false;
+
+    while bytes.get(i) == Some(&b'>') {
+        i += 1;
+        i += run_of(&line[i..], b' ');
+    }
+
+    let digits = bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+    match bytes.get(i) {
+        Some(b'-' | b'*' | b'+') if blank(i + 1) => {
+            i += 1;
+            list = true;
+        },
+        Some(_) if (1..10).contains(&digits) && matches!(bytes.get(i + digits), Some(b'.' | b')'))
+            && blank(i + digits + 1) => {
+            i += digits + 1;
+            list = true;
+        },
+        _ => {},
+    }
+    i += run_of(&line[i..], b' ');
+
+    let hashes = run_of(&line[i..], b'#');
+    if (1..7).contains(&hashes) && blank(i + hashes) {
+        i += hashes;
+        i += run_of(&line[i..], b' ');
+    }
+
+    (i.min(line.len()), list)
+}
+
+// Columns of the indentation of a line, with tabs up to the next multiple of 4
+fn columns(line: &str) -> usize {
+    line.bytes()
+        .take_while(|b| *b == b' ' || *b == b'\t')
+        .fold(0, |n, b| if b == b'\t' { n + 4 - n % 4 } else { n + 1 })
+}
+
+// Fence of a line that opens a fenced code block
+fn fence(content: &str) -> Option<(u8, usize)> {
+    let content = content.trim_start();
+    [b'`', b'~'].iter()
+        .map(|c| (*c, run_of(content, *c)))
+        // Backticks in the info string make it a code span
+        .find(|(c, n)| *n >= 3 && !(*c == b'`' && content[*n..].contains('`')))
+}
+
+// What closes the HTML block a line opens, if it does
+fn html_block(content: &str, in_paragraph: bool) -> Option<String> {
+    let content = content.trim_start();
+    if !content.starts_with('<') {
+        return None;
+    }
+    if content.starts_with("<!--") {
+        return Some("-->".to_string());
+    }
+    if content.starts_with("<?") {
+        return Some("?>".to_string());
+    }
+    if content.starts_with("<!") {
+        return Some(">".to_string());
+    }
+
+    let name: String = content[1..].trim_start_matches('/').chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match &name[..] {
+        "" => None,
+        name if HTML_RAW.contains(&name) && !content.starts_with("</") => Some(format!("</{}>", name)),
+        name if HTML_BLOCKS.contains(&name) => Some(String::new()),
+        // Any other tag alone in its line, but not in a paragraph
+        _ if !in_paragraph && content.trim_end().ends_with('>') => Some(String::new()),
+        _ => None,
+    }
+}
+
+// Label of a link reference definition like [label]: url
+fn definition(content: &str) -> Option<String> {
+    let content = content.trim_start();
+    let end = content.find("]:")?;
+    match content.starts_with('[') && !content.starts_with("[^") && end > 1 {
+        true => Some(content[1..end].trim().to_lowercase()),
+        false => None,
+    }
+}
+
+// Where the bracket at start closes, before end
+fn closing(document: &str, start: usize, end: usize, open: u8, close: u8) -> Option<usize> {
+    let bytes = document.as_bytes();
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < end {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b if b == open => depth += 1,
+            b if b == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+
+    None
+}
+
+// Inline content of a paragraph, where code spans, autolinks, inline HTML,
+// link destinations, URLs and the prefixes of its lines are markup
+struct Inline<'a> {
+    document: &'a str,
+    labels: &'a HashSet<String>,
+}
+
+impl Inline<'_> {
+    fn push(&self, text: &mut Text, range: Range<usize>, prefixes: &[Range<usize>]) {
+        let document = self.document;
+        let bytes = document.as_bytes();
+        let mut start = range.start;
+        let mut i = range.start;
+
+        while i < range.end {
+            // The quote and list markers of a line, unless they are in the
+            // middle of other markup like a code span
+            if let Ok(p) = prefixes.binary_search_by_key(&i, |p| p.start) {
+                text.push(document, start..i);
+                text.push_markup(prefixes[p].clone());
+                start = prefixes[p].end;
+                i = start;
+                continue;
+            }
+
+            // Escaped punctuation and character references are text, the
+            // rules see the character without its escape
+            let escaped = match bytes[i] {
+                b'\\' if bytes.get(i + 1).is_some_and(u8::is_ascii_punctuation) => {
+                    Some((document[i + 1..i + 2].to_string(), i + 2))
+                },
+                b'&' => markup::reference(document, i, range.end),
+                _ => None,
+            };
+            if let Some((decoded, end)) = escaped {
+                text.push(document, start..i);
+                text.push_decoded(&decoded, i..end);
+                start = end;
+                i = end;
+                continue;
+            }
+
+            let (end, inner) = match self.markup(i, range.end) {
+                Some(markup) => markup,
+                None => {
+                    // A run of backticks that doesn't open a code span is text
+                    i += match bytes[i] {
+                        b'`' => run_of(&document[i..range.end], b'`'),
+                        _ => document[i..].chars().next().map_or(1, char::len_utf8),
+                    };
+                    continue;
+                },
+            };
+
+            text.push(document, start..i);
+            match inner {
+                Some(inner) => {
+                    text.push_markup(i..inner.start);
+                    self.push(text, inner.clone(), prefixes);
+                    text.push_markup(inner.end..end);
+                },
+                None => text.push_markup(i..end),
+            }
+            start = end;
+            i = end;
+        }
+
+        text.push(document, start..range.end);
+    }
+
+    // End of the markup at start, and the text inside it, if any
+    fn markup(&self, start: usize, end: usize) -> Option<(usize, Option<Range<usize>>)> {
+        let document = self.document;
+        let bytes = document.as_bytes();
+
+        match bytes[start] {
+            b'`' => {
+                let n = run_of(&document[start..end], b'`');
+                let mut from = start + n;
+                // The code span ends at a run of as many backticks
+                while let Some(close) = document[from..end].find('`').map(|c| from + c) {
+                    let m = run_of(&document[close..end], b'`');
+                    if m == n {
+                        return Some((close + n, None));
+                    }
+                    from = close + m;
+                }
+                None
+            },
+            b'<' if bytes.get(start + 1).is_some_and(|b| b.is_ascii_alphabetic() || b"/!?".contains(b)) => {
+                document[start..end].find('>').map(|e| (start + e + 1, None))
+            },
+            b'[' => self.link(start, end),
+            b'h' | b'w' | b'f' if !document[..start].ends_with(char::is_alphanumeric) => {
+                let token = document[start..end].find(char::is_whitespace).map_or(end, |e| start + e);
+                let url = escape::trim_closing(&document[start..token]);
+                match escape::is_url(url) {
+                    true => Some((start + url.len(), None)),
+                    false => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    // End and text of the link or image whose text starts at start, that is
+    // markup when it's a reference or a footnote
+    fn link(&self, start: usize, end: usize) -> Option<(usize, Option<Range<usize>>)> {
+        let document = self.document;
+        let close = closing(document, start, end, b'[', b']')?;
+        let label = start + 1..close;
+
+        if document[label.clone()].starts_with('^') {
+            return Some((close + 1, None));
+        }
+
+        match document.as_bytes().get(close + 1) {
+            Some(b'(') => {
+                let paren = closing(document, close + 1, end, b'(', b')')?;
+                Some((paren + 1, Some(label)))
+            },
+            // [text][] uses the text as the label
+            Some(b'[') if document[close + 1..].starts_with("[]") => Some((close + 3, None)),
+            Some(b'[') => {
+                let bracket = closing(document, close + 1, end, b'[', b']')?;
+                Some((bracket + 1, Some(label)))
+            },
+            _ if self.labels.contains(&document[label].trim().to_lowercase()) => Some((close + 1, None)),
+            _ => None,
+        }
+    }
+}
+
+// Writes the paragraph of the lines, each with the length of its prefix, so
+// links and code spans can go across their line breaks
+fn write_paragraph(writer: &mut Writer, inline: &Inline, lines: &mut Vec<(Range<usize>, usize)>)
+    -> Result<(), Error> {
+    let prefixes: Vec<Range<usize>> = lines.iter()
+        .map(|(line, prefix)| line.start..line.start + prefix)
+        .filter(|prefix| !prefix.is_empty())
+        .collect();
+
+    let mut text = Text::new();
+    if let (Some((first, _)), Some((last, _))) = (lines.first(), lines.last()) {
+        inline.push(&mut text, first.start..last.end, &prefixes);
+    }
+    lines.clear();
+
+    writer.write(&text, str::to_string)
+}
+
+pub(crate) fn transliterate(pipeline: &Pipeline, input: &str, options: &EpaOptions) -> Result<String, Error> {
+    let lines = markup::lines(input);
+    let mut writer = Writer::new(pipeline, input, options);
+    let mut first = 0;
+
+    // YAML front matter
+    if lines.first().is_some_and(|l| input[l.clone()].trim_end() == "---") {
+        if let Some(end) = lines.iter().skip(1).position(|l| matches!(input[l.clone()].trim_end(), "---" | "...")) {
+            first = end + 2;
+        }
+    }
+
+    let labels: HashSet<String> = lines.iter().filter_map(|l| definition(&input[l.clone()])).collect();
+    let inline = Inline { document: input, labels: &labels };
+
+    // The lines of a paragraph go together, so the rules see their words
+    // across the line breaks
+    let mut paragraph = vec![];
+    let mut open: Option<Open> = None;
+    // Blocks like indented code can't interrupt a paragraph
+    let mut in_paragraph = false;
+    let mut in_list = false;
+    // Column of the content of the last list item, where its indented code
+    // starts 4 columns further
+    let mut list_column = 0;
+
+    for line in lines[first..].iter().cloned() {
+        let text = &input[line.clone()];
+        let (prefix, list) = prefix(text);
+        let content = &text[prefix..];
+
+        match &open {
+            Some(Open::Fence(c, n)) => {
+                if run_of(content.trim_start(), *c) >= *n && content.trim_start().trim_start_matches(*c as char)
+                    .trim().is_empty() {
+                    open = None;
+                }
+                continue;
+            },
+            Some(Open::Html(end)) => {
+                if (end.is_empty() && text.trim().is_empty())
+                    || (!end.is_empty() && text.to_ascii_lowercase().contains(&end[..])) {
+                    open = None;
+                }
+                continue;
+            },
+            None => {},
+        }
+
+        if text.trim().is_empty() {
+            write_paragraph(&mut writer, &inline, &mut paragraph)?;
+            in_paragraph = false;
+            continue;
+        }
+
+        let indented = text.starts_with("    ") || text.starts_with('\t');
+        let column = if in_list { list_column } else { 0 };
+        let code = columns(text) >= column + 4 && !in_paragraph;
+        in_list = list || (in_list && (in_paragraph || text.starts_with([' ', '\t'])));
+        if list {
+            list_column = columns(text) + text[..prefix].trim_start_matches([' ', '\t']).chars().count();
+        }
+        let html = html_block(content, in_paragraph).filter(|_| !indented);
+        let kept = code || fence(content).is_some() || html.is_some() || definition(content).is_some();
+
+        if kept {
+            write_paragraph(&mut writer, &inline, &mut paragraph)?;
+            in_paragraph = false;
+
+            // The line after the start of the opening, where an HTML block
+            // can already end
+            let rest = content.trim_start().get(2..).unwrap_or("").to_ascii_lowercase();
+            open = match (fence(content), html) {
+                (Some((c, n)), _) if !code => Some(Open::Fence(c, n)),
+                (_, Some(end)) if !end.is_empty() && rest.contains(&end[..]) => None,
+                (_, Some(end)) => Some(Open::Html(end)),
+                _ => None,
+            };
+            continue;
+        }
+
+        paragraph.push((line, prefix));
+        in_paragraph = true;
+    }
+
+    write_paragraph(&mut writer, &inline, &mut paragraph)?;
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{epa_markdown, EpaOptions};
+
+    #[test]
+    fn test_markdown() {
+        let input = "---\ntitle: Hierro\n---\n\
+                     # El hierro de Zaragoza\n\n\
+                     Lleva un *guijarrito* y un [zapato](https://example.com/hierro \"Hierro\") con `hierro`\n\
+                     y [Chorizo][1], <b>hola</b> en https://hierro.es. \\*Hierro\\* &aacute;rabe\n\n\
+                     > - Jerez y [hierro]\n\
+                     > - ![Zapato](zapato.png)\n\n\
+                     ```rust\nlet hierro = \"zapato\";\n```\n\n    hierro indentado\n\n\
+                     <div>\nhierro\n</div>\n\n\
+                     [1]: https://chorizo.es\n[hierro]: https://hierro.es\n";
+
+        let expected = "---\ntitle: Hierro\n---\n\
+                        # El ierro de Çaragoça\n\n\
+                        Yeba un *giharrito* y un [çapato](https://example.com/hierro \"Hierro\") con `hierro`\n\
+                        y [Xoriço][1], <b>ola</b> en https://hierro.es. \\*Ierro\\* &aacute;rabe\n\n\
+                        > - Herêh y [hierro]\n\
+                        > - ![Çapato](zapato.png)\n\n\
+                        ```rust\nlet hierro = \"zapato\";\n```\n\n    hierro indentado\n\n\
+                        <div>\nhierro\n</div>\n\n\
+                        [1]: https://chorizo.es\n[hierro]: https://hierro.es\n";
+
+        assert_eq!(epa_markdown(input, &EpaOptions::default()).expect("Wrong parser"), expected);
+
+        // Indented code of a list item starts 4 columns after its content
+        let input = "1. Hola\n\n        code zapato\n\n    Hola zapato\n";
+        let expected = "1. Ola\n\n        code zapato\n\n    Ola çapato\n";
+        assert_eq!(epa_markdown(input, &EpaOptions::default()).expect("Wrong parser"), expected);
+
+        // Links and code spans go on in the next line of their paragraph
+        let input = "> Un [zapato de\n> hierro](/zapato-hierro.html) y `zapato de\nhierro` de Zaragoza\n";
+        let expected = "> Un [çapato de\n> ierro](/zapato-hierro.html) y `zapato de\nhierro` de Çaragoça\n";
+        assert_eq!(epa_markdown(input, &EpaOptions::default()).expect("Wrong parser"), expected);
+    }
+}
@@ -0,0 +1,190 @@
+// Graphemes of the EPA the last step writes as the options ask, followed
+// through the steps of the pipeline. The rules always work with EPA's ç and h,
+// so every table keyed with them keeps matching, but the ç the input already
+// had must reach the output as they are, and only the h gj_rule writes for the
+// VVF are the ones to write with j.
+
+use std::ops::Range;
+
+use crate::{EpaOptions, Match, Rule, Trace, Vaf, Vvf};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Marks {
+    // Offsets of the ç of the input
+    pub(crate) cedillas: Vec<usize>,
+    // Offsets of the h written for the VVF
+    pub(crate) vvf: Vec<usize>,
+}
+
+impl Marks {
+    // Whether the options write a grapheme of the EPA another way
+    pub(crate) fn needed(options: &EpaOptions) -> bool {
+        options.vaf != Vaf::Cedilla || options.vvf != Vvf::H
+    }
+
+    // The marks of a text no rule has rewritten yet
+    pub(crate) fn of(input: &str, options: &EpaOptions) -> Marks {
+        let cedillas = match options.vaf {
+            Vaf::Cedilla => vec![],
+            _ => input.match_indices(['ç', 'Ç']).map(|(i, _)| i).collect(),
+        };
+
+        Marks { cedillas, vvf: vec![] }
+    }
+
+    // The marks of a piece of the text, from its start
+    pub(crate) fn within(&self, range: &Range<usize>) -> Marks {
+        let within = |marks: &[usize]| {
+            marks.iter().filter(|i| range.contains(i)).map(|i| i - range.start).collect()
+        };
+
+        Marks { cedillas: within(&self.cedillas), vvf: within(&self.vvf) }
+    }
+
+    // Adds the marks of a piece that starts at offset
+    pub(crate) fn extend(&mut self, marks: &Marks, offset: usize) {
+        self.cedillas.extend(marks.cedillas.iter().map(|i| i + offset));
+        self.vvf.extend(marks.vvf.iter().map(|i| i + offset));
+    }
+
+    // The marks of the output of a step, from the ones of its input and the
+    // pieces its matches rewrote
+    pub(crate) fn follow(&self, input: &str, matches: &[Match]) -> Marks {
+        Marks {
+            cedillas: follow(&self.cedillas, ['ç', 'Ç'], input, matches, |_| false),
+            vvf: follow(&self.vvf, ['h', 'H'], input, matches, |m| m.rule == Some(Rule::GJV)),
+        }
+    }
+
+    // Writes the ç but the marked ones with the VAF, and the marked h with the
+    // VVF, of a piece of the text at offset
+    pub(crate) fn write(&self, text: &str, offset: usize, options: &EpaOptions, mut trace: Trace) -> String {
+        let (vaf, vvf) = (options.vaf.as_str(), options.vvf.as_str());
+        if options.vaf == Vaf::Cedilla && options.vvf == Vvf::H {
+            return text.to_string();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        for (i, c) in text.char_indices() {
+            let marked = |marks: &[usize]| marks.binary_search(&(offset + i)).is_ok();
+            let grapheme = match c {
+                'ç' | 'Ç' if options.vaf != Vaf::Cedilla && !marked(&self.cedillas) => vaf,
+                'h' | 'H' if options.vvf != Vvf::H && marked(&self.vvf) => vvf,
+                _ => {
+                    output.push(c);
+                    continue;
+                },
+            };
+
+            let grapheme = match c.is_uppercase() {
+                true => grapheme.to_uppercase(),
+                false => grapheme.to_string(),
+            };
+            if let Some(t) = trace.as_deref_mut() {
+                t.push(Match { rule: None, span: i..i + c.len_utf8(), output: grapheme.clone() });
+            }
+            output += &grapheme;
+        }
+
+        output
+    }
+}
+
+// Reads the j before a vowel of a text written with the VVF j as EPA's h, for
+// a rule that runs after gj_rule on its own
+pub(crate) fn read_vvf(input: &str, options: &EpaOptions) -> (String, Marks) {
+    let mut marks = Marks::default();
+    if options.vvf == Vvf::H {
+        return (input.to_string(), marks);
+    }
+
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let before_vowel = chars.peek().is_some_and(|(_, next)| "aeiouáéíóúâêîôûAEIOUÁÉÍÓÚÂÊÎÔÛ".contains(*next));
+        match c {
+            'j' | 'J' if before_vowel => {
+                output.push(if c == 'j' { 'h' } else { 'H' });
+                marks.vvf.push(i);
+            },
+            _ => output.push(c),
+        }
+    }
+
+    (output, marks)
+}
+
+// Offsets of the marked graphemes of a class after a step. A piece a match
+// rewrote gives its marks in order to the graphemes it writes. When it writes
+// more of them, each one takes the mark of the grapheme at its position in
+// the piece, and one that isn't there takes the mark of the grapheme right
+// after it, like the consonant a digraph doubles, or none, like the h of an
+// aspirated ending. The graphemes of the matches written are all marked.
+fn follow<F>(marked: &[usize], class: [char; 2], input: &str, matches: &[Match], written: F) -> Vec<usize>
+    where F: Fn(&Match) -> bool {
+    let mut output = vec![];
+    let mut marked = marked.iter().copied().peekable();
+    let mut shift = 0;
+
+    for m in matches {
+        while let Some(i) = marked.next_if(|i| *i < m.span.start) {
+            output.push((i as isize + shift) as usize);
+        }
+
+        // Position in characters and mark of the graphemes of the piece
+        let piece = &input[m.span.clone()];
+        let flags: Vec<(usize, bool)> = piece.match_indices(class)
+            .map(|(i, _)| (piece[..i].chars().count(), marked.next_if_eq(&(m.span.start + i)).is_some()))
+            .collect();
+        while marked.next_if(|i| *i < m.span.end).is_some() {}
+
+        let start = (m.span.start as isize + shift) as usize;
+        let graphemes: Vec<(usize, usize)> = m.output.match_indices(class)
+            .map(|(i, _)| (i, m.output[..i].chars().count()))
+            .collect();
+        let n = graphemes.len();
+        let mut marks = vec![false; n];
+        for k in (0..n).rev() {
+            marks[k] = match n > flags.len() {
+                true => {
+                    let (i, position) = graphemes[k];
+                    match flags.iter().find(|(p, _)| *p == position) {
+                        Some((_, flag)) => *flag,
+                        None => {
+                            let next = i + m.output[i..].chars().next().map_or(1, char::len_utf8);
+                            k + 1 < n && graphemes[k + 1].0 == next && marks[k + 1]
+                        },
+                    }
+                },
+                false => flags[k + flags.len() - n].1,
+            };
+        }
+        for ((i, _), flag) in graphemes.into_iter().zip(marks) {
+            if written(m) || flag {
+                output.push(start + i);
+            }
+        }
+
+        shift += m.output.len() as isize - m.span.len() as isize;
+    }
+
+    output.extend(marked.map(|i| (i as isize + shift) as usize));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follow() {
+        let matches = vec![
+            Match { rule: None, span: 0..2, output: "çe".to_string() },
+            Match { rule: None, span: 7..11, output: "âçç".to_string() },
+        ];
+        let marks = Marks { cedillas: vec![3, 9, 12], vvf: vec![] };
+
+        // A ç a rule wrote isn't marked, and a doubled one takes the mark
+        assert_eq!(marks.follow("ze ça acç ç", &matches).cedillas, vec![4, 10, 12, 15]);
+    }
+}
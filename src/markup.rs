@@ -0,0 +1,272 @@
+// Text a document shows, made of pieces of it, so the rules only see the
+// text and their output is written back into the document keeping the
+// markup and the escapes of the text they didn't change.
+
+use std::ops::Range;
+
+use pest::Parser;
+
+use crate::pipeline::Pipeline;
+use crate::{AndaluhParser, EpaOptions, Error, Rule};
+
+// Named character references of the text of most documents
+const ENTITIES: &[(&str, &str)] = &[
+    ("amp", "&"), ("lt", "<"), ("gt", ">"), ("quot", "\""), ("apos", "'"), ("nbsp", "\u{a0}"),
+    ("aacute", "á"), ("eacute", "é"), ("iacute", "í"), ("oacute", "ó"), ("uacute", "ú"),
+    ("Aacute", "Á"), ("Eacute", "É"), ("Iacute", "Í"), ("Oacute", "Ó"), ("Uacute", "Ú"),
+    ("ntilde", "ñ"), ("Ntilde", "Ñ"), ("uuml", "ü"), ("Uuml", "Ü"), ("ccedil", "ç"), ("Ccedil", "Ç"),
+    ("iexcl", "¡"), ("iquest", "¿"), ("laquo", "«"), ("raquo", "»"), ("ordf", "ª"), ("ordm", "º"),
+    ("ndash", "–"), ("mdash", "—"), ("hellip", "…"), ("lsquo", "‘"), ("rsquo", "’"), ("ldquo", "“"),
+    ("rdquo", "”"), ("middot", "·"), ("euro", "€"), ("copy", "©"), ("reg", "®"), ("shy", "\u{ad}"),
+];
+
+// Character of the reference without its & and ;, or nothing for a number
+// that isn't one
+fn decode(reference: &str) -> Option<String> {
+    let code = match reference.strip_prefix('#') {
+        Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+        Some(decimal) => decimal.parse().ok(),
+        None => return ENTITIES.iter().find(|(name, _)| *name == reference).map(|(_, c)| c.to_string()),
+    };
+
+    Some(code.and_then(char::from_u32).map(String::from).unwrap_or_default())
+}
+
+// Whether the rules can parse the text, that has no control character or
+// other one the grammar doesn't know
+fn readable(text: &str) -> bool {
+    let mut buf = [0; 4];
+    !text.is_empty() && text.chars().all(|c| AndaluhParser::parse(Rule::bad_char, c.encode_utf8(&mut buf)).is_err())
+}
+
+// Character of the reference like &aacute; or &#225; at start, and where it
+// ends, when it does before end
+pub(crate) fn reference(document: &str, start: usize, end: usize) -> Option<(String, usize)> {
+    let name_end = document.as_bytes()[start + 1..end].iter()
+        .position(|b| !b.is_ascii_alphanumeric() && *b != b'#')
+        .map_or(end, |i| start + 1 + i);
+    match document.as_bytes().get(name_end) {
+        Some(b';') if name_end < end => decode(&document[start + 1..name_end]).map(|c| (c, name_end + 1)),
+        _ => None,
+    }
+}
+
+// Escapes what HTML and WebVTT would read as markup, and the quote of an
+// attribute
+pub(crate) fn escape(text: &str, quote: Option<char>) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if quote == Some('"') => escaped.push_str("&quot;"),
+            '\'' if quote == Some('\'') => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// Whether a language tag like es-ES is Spanish, or unknown when empty
+pub(crate) fn is_spanish(lang: &str) -> bool {
+    let lang = lang.trim().to_ascii_lowercase();
+    lang.is_empty() || lang == "es" || lang.starts_with("es-")
+}
+
+// Byte ranges of the lines of input, with their line breaks
+pub(crate) fn lines(input: &str) -> Vec<Range<usize>> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for line in input.split_inclusive('\n') {
+        lines.push(start..start + line.len());
+        start += line.len();
+    }
+
+    lines
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Text {
+    text: String,
+    // Range in text and in the document of every piece of text, and whether
+    // it's written as it is, unlike escapes like &aacute;
+    pieces: Vec<(Range<usize>, Range<usize>, bool)>,
+    // Markup in the middle of the text, like an inline tag, with the offset
+    // of the text where it is
+    markup: Vec<(usize, Range<usize>)>,
+    source: Option<Range<usize>>,
+}
+
+impl Text {
+    pub(crate) fn new() -> Text {
+        Text::default()
+    }
+
+    fn extend_source(&mut self, source: &Range<usize>) {
+        self.source = match self.source.take() {
+            Some(s) => Some(s.start..source.end),
+            None => Some(source.clone()),
+        };
+    }
+
+    // Text written as it is in the document
+    pub(crate) fn push(&mut self, document: &str, source: Range<usize>) {
+        if source.is_empty() {
+            return;
+        }
+
+        let start = self.text.len();
+        self.text.push_str(&document[source.clone()]);
+        self.extend_source(&source);
+        self.pieces.push((start..self.text.len(), source, true));
+    }
+
+    // Text written escaped in the document, that stays markup when the rules
+    // can't read it
+    pub(crate) fn push_decoded(&mut self, text: &str, source: Range<usize>) {
+        if !readable(text) {
+            return self.push_markup(source);
+        }
+
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.extend_source(&source);
+        self.pieces.push((start..self.text.len(), source, false));
+    }
+
+    // Text with HTML character references, that the rules see decoded
+    pub(crate) fn push_html(&mut self, document: &str, range: Range<usize>) {
+        let mut start = range.start;
+        let mut i = range.start;
+
+        while let Some(amp) = document[i..range.end].find('&').map(|a| i + a) {
+            i = match reference(document, amp, range.end) {
+                Some((decoded, end)) => {
+                    self.push(document, start..amp);
+                    self.push_decoded(&decoded, amp..end);
+                    start = end;
+                    end
+                },
+                None => amp + 1,
+            };
+        }
+
+        self.push(document, start..range.end);
+    }
+
+    pub(crate) fn push_markup(&mut self, source: Range<usize>) {
+        self.extend_source(&source);
+        self.markup.push((self.text.len(), source));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.source.is_none()
+    }
+
+    // Whether the rules have nothing to see
+    pub(crate) fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    // Range of the document it covers
+    pub(crate) fn source(&self) -> Range<usize> {
+        self.source.clone().unwrap_or(0..0)
+    }
+
+    // Offset in the document of an offset of the text, after the markup there
+    fn source_of(&self, offset: usize) -> usize {
+        let i = self.pieces.partition_point(|(text, _, _)| text.end <= offset);
+        match self.pieces.get(i) {
+            Some((text, source, true)) => source.start + offset.max(text.start) - text.start,
+            Some((_, source, false)) => source.start,
+            None => self.source().end,
+        }
+    }
+
+    // Transliterates the text, returning the range of the document it covers
+    // written back, with the text the rules changed escaped
+    pub(crate) fn transliterate<F>(&self, pipeline: &Pipeline, document: &str, options: &EpaOptions, escape: F)
+        -> Result<String, Error>
+        where F: Fn(&str) -> String {
+        let source = self.source();
+        if self.is_blank() {
+            return Ok(document[source].to_string());
+        }
+
+        let (output, alignment) = pipeline.transliterate_aligned(&self.text, options)
+            .map_err(|e| e.relocated(document, |offset| self.source_of(offset)))?;
+
+        let mut written = document[source.start..self.source_of(0)].to_string();
+        for a in alignment {
+            let start = self.source_of(a.source.start);
+            let end = self.source_of(a.source.end);
+            if !a.changed {
+                written += &document[start..end];
+                continue;
+            }
+
+            // Markup in the middle of a word stays after as many characters
+            // of its output as there were before it
+            let out = &output[a.output.clone()];
+            let mut cut = 0;
+            for (at, markup) in self.markup.iter().filter(|(at, _)| a.source.start < *at && *at <= a.source.end) {
+                let chars = self.text[a.source.start..*at].chars().count();
+                let next = match *at == a.source.end {
+                    true => out.len(),
+                    false => out.char_indices().nth(chars).map_or(out.len(), |(i, _)| i).max(cut),
+                };
+                written += &escape(&out[cut..next]);
+                written += &document[markup.clone()];
+                cut = next;
+            }
+            written += &escape(&out[cut..]);
+        }
+
+        Ok(written)
+    }
+}
+
+// A document written back with its texts transliterated
+pub(crate) struct Writer<'a> {
+    pipeline: &'a Pipeline,
+    document: &'a str,
+    options: &'a EpaOptions,
+    output: String,
+    // Bytes of the document already written
+    written: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn new(pipeline: &'a Pipeline, document: &'a str, options: &'a EpaOptions) -> Writer<'a> {
+        let output = String::with_capacity(document.len());
+        Writer { pipeline, document, options, output, written: 0 }
+    }
+
+    // Writes the document up to the end of text, with text transliterated
+    // and what the rules changed escaped
+    pub(crate) fn write<F: Fn(&str) -> String>(&mut self, text: &Text, escape: F) -> Result<(), Error> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let source = text.source();
+        self.output += &self.document[self.written..source.start];
+        self.output += &text.transliterate(self.pipeline, self.document, self.options, escape)?;
+        self.written = source.end;
+        Ok(())
+    }
+
+    // Writes the document up to at, and then text
+    pub(crate) fn insert(&mut self, at: usize, text: &str) {
+        self.output += &self.document[self.written..at];
+        self.output += text;
+        self.written = at;
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        self.output += &self.document[self.written..];
+        self.output
+    }
+}
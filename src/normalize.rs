@@ -0,0 +1,193 @@
+// Unicode normalisation, so the grammar always sees precomposed letters like
+// á, even in text copied from sources that decompose them (NFD).
+
+use std::borrow::Cow;
+use std::ops::Range;
+use std::str::FromStr;
+
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{Alignment, Error, Match};
+
+/// Unicode normalisation form of the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Composed, the form the rules work with
+    #[default]
+    Nfc,
+    /// Decomposed, with the accents as combining characters
+    Nfd,
+}
+
+impl FromStr for Normalization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Normalization, Error> {
+        match &s.to_lowercase()[..] {
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            _ => Err(Error::InvalidConfig(format!("unknown normalization {:?}", s))),
+        }
+    }
+}
+
+// The input composed, with the graphemes that changed
+pub(crate) struct Normalized<'a> {
+    pub(crate) text: Cow<'a, str>,
+    // Byte ranges of every grapheme that changed, in the input and in text
+    pieces: Vec<(Range<usize>, Range<usize>)>,
+}
+
+pub(crate) fn nfc(input: &str) -> Normalized<'_> {
+    if is_nfc(input) {
+        return Normalized { text: Cow::Borrowed(input), pieces: vec![] };
+    }
+
+    // Graphemes never compose with each other, so they are composed alone
+    let mut text = String::with_capacity(input.len());
+    let mut pieces = vec![];
+    for (i, grapheme) in input.grapheme_indices(true) {
+        let start = text.len();
+        text.extend(grapheme.nfc());
+        if text[start..] != *grapheme {
+            pieces.push((i..i + grapheme.len(), start..text.len()));
+        }
+    }
+
+    Normalized { text: Cow::Owned(text), pieces }
+}
+
+impl Normalized<'_> {
+    // Offset in the input of an offset of text, where the rules start a piece
+    pub(crate) fn source_start(&self, offset: usize) -> usize {
+        let i = self.pieces.partition_point(|(_, text)| text.end <= offset);
+        match self.pieces.get(i) {
+            Some((source, text)) if text.start < offset => source.start,
+            _ => self.shifted(i, offset),
+        }
+    }
+
+    // Offset in the input of an offset of text, where the rules end a piece
+    pub(crate) fn source_end(&self, offset: usize) -> usize {
+        let i = self.pieces.partition_point(|(_, text)| text.end < offset);
+        match self.pieces.get(i) {
+            Some((source, text)) if text.start < offset => source.end,
+            _ => self.shifted(i, offset),
+        }
+    }
+
+    // Offset after the first n pieces
+    fn shifted(&self, n: usize, offset: usize) -> usize {
+        match n.checked_sub(1).map(|i| &self.pieces[i]) {
+            Some((source, text)) => offset - text.end + source.end,
+            None => offset,
+        }
+    }
+
+    pub(crate) fn source_range(&self, range: &Range<usize>) -> Range<usize> {
+        self.source_start(range.start)..self.source_end(range.end)
+    }
+
+    // Alignment of the output with the input, from the one with the text,
+    // where the graphemes that changed are pieces of their own
+    pub(crate) fn alignment(&self, alignment: Vec<Alignment>) -> Vec<Alignment> {
+        if self.pieces.is_empty() {
+            return alignment;
+        }
+
+        let mut aligned = vec![];
+        for a in alignment {
+            if a.changed {
+                aligned.push(Alignment { source: self.source_range(&a.source), ..a });
+                continue;
+            }
+
+            let output = |offset: usize| offset - a.source.start + a.output.start;
+            let unchanged = |start: usize, end: usize| Alignment {
+                source: self.source_start(start)..self.source_end(end),
+                output: output(start)..output(end),
+                changed: false,
+            };
+
+            let mut start = a.source.start;
+            let first = self.pieces.partition_point(|(_, text)| text.end <= a.source.start);
+            for (source, text) in self.pieces[first..].iter().take_while(|(_, text)| text.start < a.source.end) {
+                let from = text.start.max(a.source.start);
+                let to = text.end.min(a.source.end);
+                if from > start {
+                    aligned.push(unchanged(start, from));
+                }
+                aligned.push(Alignment { source: source.clone(), output: output(from)..output(to), changed: true });
+                start = to;
+            }
+            if start < a.source.end {
+                aligned.push(unchanged(start, a.source.end));
+            }
+        }
+
+        aligned
+    }
+
+    // The error of the rules, in the input
+    pub(crate) fn error(&self, error: Error, input: &str) -> Error {
+        match self.pieces.is_empty() {
+            true => error,
+            false => error.relocated(input, |offset| self.source_start(offset)),
+        }
+    }
+}
+
+// Writes the output in the form of the options, recording in trace the
+// graphemes that changed
+pub(crate) fn output(input: &str, normalization: Normalization, trace: Option<&mut Vec<Match>>) -> String {
+    if normalization == Normalization::Nfc {
+        return input.to_string();
+    }
+
+    let mut output = String::with_capacity(input.len());
+    let mut matches = vec![];
+    for (i, grapheme) in input.grapheme_indices(true) {
+        let start = output.len();
+        output.extend(grapheme.nfd());
+        if output[start..] != *grapheme {
+            matches.push(Match { rule: None, span: i..i + grapheme.len(), output: output[start..].to_string() });
+        }
+    }
+
+    if let Some(t) = trace {
+        t.extend(matches);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{epa, epa_aligned, epa_with, EpaOptions, Error, Normalization};
+
+    #[test]
+    fn test_normalization() {
+        let composed = "Cádiz, güisqui y Zaragoza";
+        let decomposed = "Ca\u{301}diz, gu\u{308}isqui y Zaragoza";
+        let expected = epa(composed).expect("Wrong parser");
+        assert_eq!(expected, "Cádî, guîqqui y Çaragoça");
+        assert_eq!(epa(decomposed).expect("Wrong parser"), expected);
+
+        let options = EpaOptions { normalization: Normalization::Nfd, ..Default::default() };
+        let output = epa_with(composed, &options).expect("Wrong parser");
+        assert_eq!(output, "Ca\u{301}di\u{302}, gui\u{302}qqui y C\u{327}aragoc\u{327}a");
+
+        let (output, alignment) = epa_aligned(decomposed, &EpaOptions::default()).expect("Wrong parser");
+        let pieces: Vec<(&str, &str)> = alignment.iter()
+            .filter(|a| a.changed)
+            .map(|a| (&decomposed[a.source.clone()], &output[a.output.clone()]))
+            .collect();
+        assert_eq!(pieces[0], ("Ca\u{301}diz", "Cádî"));
+
+        match epa("Ca\u{301}diz \u{7}") {
+            Err(Error::Grammar { offset, line, column, .. }) => assert_eq!((offset, line, column), (8, 1, 8)),
+            other => panic!("Wrong parser {:?}", other),
+        }
+    }
+}
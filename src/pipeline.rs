@@ -0,0 +1,594 @@
+// Rules as trait objects, so the pipeline of a proposal can be changed and
+// extended with rules from outside the crate.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{align, defs, html, ipa, markdown, normalize, po, protect, resources, subtitles,
+            vaf_output, Alignment, Marks, EpaOptions, Error, HtmlOptions, IpaOptions, Match, Normalization, PoOptions,
+            Resource, RuleFn, Step, Subtitles, Vaf, Vvf, Warning, EPA_RULES};
+
+/// A step of the transliteration pipeline
+///
+/// ```
+/// use andaluh::{EpaOptions, Error};
+/// use andaluh::pipeline::{Pipeline, PipelineRule};
+///
+/// struct Seseo;
+///
+/// impl PipelineRule for Seseo {
+///     fn name(&self) -> &str {
+///         "seseo"
+///     }
+///
+///     fn apply(&self, input: &str, _: &EpaOptions) -> Result<String, Error> {
+///         Ok(input.replace('z', "s"))
+///     }
+/// }
+///
+/// let mut pipeline = Pipeline::new();
+/// pipeline.push(Seseo).expect("Wrong rule");
+/// let output = pipeline.transliterate("zapato", &EpaOptions::default()).expect("Wrong parser");
+/// assert_eq!(output, "sapato");
+/// ```
+pub trait PipelineRule: Send + Sync {
+    /// Name of the rule, unique in a pipeline
+    fn name(&self) -> &str;
+
+    /// Rewrites a piece of the text, without the words of the exception table
+    /// and the text the options keep
+    fn apply(&self, input: &str, options: &EpaOptions) -> Result<String, Error>;
+
+    /// Replacement of a whole lowercase word, that `apply` never sees
+    fn exception(&self, _word: &str, _options: &EpaOptions) -> Option<String> {
+        None
+    }
+
+    /// Whether the rule gives the same output for a text and for each of its
+    /// words alone, so it can run word by word
+    fn word_level(&self) -> bool {
+        false
+    }
+
+    /// Rewrites the whole text of the step, recording in trace the pieces that
+    /// changed
+    fn apply_traced(&self, input: &str, options: &EpaOptions, trace: Option<&mut Vec<Match>>)
+        -> Result<String, Error> {
+        let spans = protect::spans_with(input, options, |word| self.exception(word, options));
+        protect::transliterate(spans, trace, |text, trace| {
+            let output = self.apply(text, options)?;
+            if let Some(t) = trace.filter(|_| output != text) {
+                t.push(Match { rule: None, span: 0..text.len(), output: output.clone() });
+            }
+
+            Ok(output)
+        })
+    }
+}
+
+// The rules of the crate, that trace the grammar rules that fire
+struct Builtin {
+    name: &'static str,
+    rule: RuleFn,
+    exceptions: Option<&'static HashMap<&'static str, &'static str>>,
+    word_level: bool,
+}
+
+impl PipelineRule for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn apply(&self, input: &str, options: &EpaOptions) -> Result<String, Error> {
+        (self.rule)(input, options, None)
+    }
+
+    fn exception(&self, word: &str, _: &EpaOptions) -> Option<String> {
+        self.exceptions.and_then(|map| map.get(word)).map(|w| w.to_string())
+    }
+
+    fn word_level(&self) -> bool {
+        self.word_level
+    }
+
+    fn apply_traced(&self, input: &str, options: &EpaOptions, trace: Option<&mut Vec<Match>>)
+        -> Result<String, Error> {
+        (self.rule)(input, options, trace)
+    }
+}
+
+// Name and words of the exception table of a rule of the crate
+pub(crate) fn exception_table(name: &str) -> Option<(&'static str, &'static HashMap<&'static str, &'static str>)> {
+    match name {
+        "h_rule" => Some(("H_RULES_EXCEPT", &defs::H_RULES_EXCEPT)),
+        "gj_rule" => Some(("GJ_RULES_EXCEPT", &defs::GJ_RULES_EXCEPT)),
+        "v_rule" => Some(("V_RULES_EXCEPT", &defs::V_RULES_EXCEPT)),
+        "ll_rule" => Some(("LL_RULES_EXCEPT", &defs::LL_RULES_EXCEPT)),
+        "exception_rule" => Some(("ENDING_RULES_EXCEPTION", &defs::ENDING_RULES_EXCEPTION)),
+        _ => None,
+    }
+}
+
+// Runs a rule, with the words the options add to its exceptions replaced
+// before it sees them
+fn run_rule(rule: &dyn PipelineRule, input: &str, options: &EpaOptions, trace: Option<&mut Vec<Match>>)
+    -> Result<String, Error> {
+    match options.rule_exceptions.get(rule.name()).filter(|words| !words.is_empty()) {
+        Some(words) => {
+            let spans = protect::spans_with(input, options, |word| words.get(word).map(String::from));
+            protect::transliterate(spans, trace, |text, trace| rule.apply_traced(text, options, trace))
+        },
+        None => rule.apply_traced(input, options, trace),
+    }
+}
+
+/// Rules run one after the other over the whole text, before writing the VAF
+/// grapheme and the words of the user dictionary
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    rules: Vec<Arc<dyn PipelineRule>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// The rules of the EPA proposal
+    pub fn epa() -> Pipeline {
+        let rules = EPA_RULES.iter()
+            .map(|(name, rule, word_level)| {
+                let exceptions = exception_table(name).map(|(_, map)| map);
+                let builtin = Builtin { name, rule: *rule, exceptions, word_level: *word_level };
+                Arc::new(builtin) as Arc<dyn PipelineRule>
+            }).collect();
+
+        Pipeline { rules }
+    }
+
+    /// Names of the rules, in the order they run
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(|r| r.name())
+    }
+
+    fn position(&self, name: &str) -> Result<usize, Error> {
+        self.names().position(|n| n == name)
+            .ok_or_else(|| Error::InvalidConfig(format!("unknown rule {:?}", name)))
+    }
+
+    pub fn insert<R: PipelineRule + 'static>(&mut self, index: usize, rule: R) -> Result<(), Error> {
+        if self.names().any(|n| n == rule.name()) {
+            return Err(Error::InvalidConfig(format!("rule {:?} already in the pipeline", rule.name())));
+        }
+        if index > self.rules.len() {
+            return Err(Error::InvalidConfig(format!("no position {} in the pipeline", index)));
+        }
+
+        self.rules.insert(index, Arc::new(rule));
+        Ok(())
+    }
+
+    pub fn push<R: PipelineRule + 'static>(&mut self, rule: R) -> Result<(), Error> {
+        self.insert(self.rules.len(), rule)
+    }
+
+    pub fn insert_before<R: PipelineRule + 'static>(&mut self, name: &str, rule: R) -> Result<(), Error> {
+        let index = self.position(name)?;
+        self.insert(index, rule)
+    }
+
+    pub fn insert_after<R: PipelineRule + 'static>(&mut self, name: &str, rule: R) -> Result<(), Error> {
+        let index = self.position(name)?;
+        self.insert(index + 1, rule)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), Error> {
+        let index = self.position(name)?;
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    /// Runs the rules in this order, that must name every rule of the pipeline
+    pub fn reorder<S: AsRef<str>>(&mut self, names: &[S]) -> Result<(), Error> {
+        if names.len() != self.rules.len() {
+            return Err(Error::InvalidConfig(
+                format!("{} rules to reorder, the pipeline has {}", names.len(), self.rules.len())));
+        }
+
+        let mut rules = Vec::with_capacity(names.len());
+        for name in names {
+            let name = name.as_ref();
+            if rules.iter().any(|r: &Arc<dyn PipelineRule>| r.name() == name) {
+                return Err(Error::InvalidConfig(format!("rule {:?} twice", name)));
+            }
+            rules.push(self.rules[self.position(name)?].clone());
+        }
+
+        self.rules = rules;
+        Ok(())
+    }
+
+    pub fn transliterate(&self, input: &str, options: &EpaOptions) -> Result<String, Error> {
+        if options.lenient {
+            return self.transliterate_lenient(input, options).map(|(output, _)| output);
+        }
+
+        let normalized = normalize::nfc(input);
+        let output = self.transliterate_composed(&normalized.text, options)
+            .map_err(|e| normalized.error(e, input))?;
+
+        Ok(normalize::output(&output, options.normalization, None))
+    }
+
+    // Tokenises the text once and runs every group of word level rules over
+    // each distinct word, instead of parsing the whole text with each rule
+    fn transliterate_composed(&self, input: &str, options: &EpaOptions) -> Result<String, Error> {
+        let mut output = input.to_string();
+        let mut marks = Marks::of(input, options);
+        let mut rules = &self.rules[..];
+
+        while let Some(rule) = rules.first() {
+            let n = rules.iter().take_while(|r| r.word_level()).count();
+            let (out, out_marks) = match n {
+                0 => run_marked(rule.as_ref(), &output, &marks, options)?,
+                _ => by_words(&output, &marks, options, &rules[..n])?,
+            };
+            output = out;
+            marks = out_marks;
+            rules = &rules[n.max(1)..];
+        }
+
+        vaf_output(&output, options, None, &marks)
+    }
+
+    // The rules one after the other over the whole text, that the words give
+    // the same output as
+    #[cfg(test)]
+    fn transliterate_by_steps(&self, input: &str, options: &EpaOptions) -> Result<String, Error> {
+        let mut output = input.to_string();
+        let mut marks = Marks::of(input, options);
+        for rule in self.rules.iter() {
+            let (out, out_marks) = run_marked(rule.as_ref(), &output, &marks, options)?;
+            output = out;
+            marks = out_marks;
+        }
+
+        vaf_output(&output, options, None, &marks)
+    }
+
+    /// Transliterates in the lenient mode, returning the text left untouched
+    pub fn transliterate_lenient(&self, input: &str, options: &EpaOptions)
+        -> Result<(String, Vec<Warning>), Error> {
+        let normalized = normalize::nfc(input);
+        let text = &normalized.text[..];
+        let options = EpaOptions { lenient: false, ..options.clone() };
+
+        // The words the grammar can't parse are kept once for every rule, and
+        // the text between them goes through the pipeline
+        let mut output = String::with_capacity(text.len());
+        let mut warnings = vec![];
+        let mut start = 0;
+        for (span, warning) in protect::kept(text, &options) {
+            output += &self.transliterate_composed(&text[start..span.start], &options)
+                .map_err(|e| normalized.error(e.after(&text[..start]), input))?;
+            output += &text[span.clone()];
+            warnings.push(Warning { span: normalized.source_range(&span), ..warning });
+            start = span.end;
+        }
+        output += &self.transliterate_composed(&text[start..], &options)
+            .map_err(|e| normalized.error(e.after(&text[..start]), input))?;
+
+        Ok((normalize::output(&output, options.normalization, None), warnings))
+    }
+
+    /// Transliterates returning where every piece of the output comes from
+    pub fn transliterate_aligned(&self, input: &str, options: &EpaOptions)
+        -> Result<(String, Vec<Alignment>), Error> {
+        let steps = self.trace(input, options)?;
+        let output = steps.last().map_or_else(String::new, |s| s.output.clone());
+
+        // The steps start from the input composed
+        let normalized = normalize::nfc(input);
+        let alignment = normalized.alignment(align::align(&normalized.text, &steps));
+
+        Ok((output, alignment))
+    }
+
+    /// Transliterates the text of an HTML document, keeping its markup as it
+    /// is
+    pub fn transliterate_html(&self, input: &str, options: &EpaOptions, html_options: &HtmlOptions)
+        -> Result<String, Error> {
+        html::transliterate(self, input, options, html_options)
+    }
+
+    /// Transliterates the prose of a Markdown document, keeping code, URLs,
+    /// HTML blocks and the front matter as they are
+    pub fn transliterate_markdown(&self, input: &str, options: &EpaOptions) -> Result<String, Error> {
+        markdown::transliterate(self, input, options)
+    }
+
+    /// Transliterates the text of the cues of SRT or WebVTT subtitles,
+    /// keeping their timings and styling tags as they are
+    pub fn transliterate_subtitles(&self, input: &str, options: &EpaOptions, format: Subtitles)
+        -> Result<String, Error> {
+        subtitles::transliterate(self, input, options, format)
+    }
+
+    /// Transliterates the translations of a gettext PO catalogue, keeping the
+    /// placeholders, comments and flags as they are
+    pub fn transliterate_po(&self, input: &str, options: &EpaOptions, po_options: &PoOptions)
+        -> Result<String, Error> {
+        po::transliterate(self, input, options, po_options)
+    }
+
+    /// Transliterates the string values of a JSON or YAML locale file,
+    /// keeping the keys, the ICU MessageFormat syntax and the placeholders as
+    /// they are
+    pub fn transliterate_resource(&self, input: &str, options: &EpaOptions, format: Resource)
+        -> Result<String, Error> {
+        resources::transliterate(self, input, options, format)
+    }
+
+    /// Broad IPA transcription of the pronunciation the EPA spelling writes
+    pub fn transcribe_ipa(&self, input: &str, options: &EpaOptions, ipa_options: &IpaOptions)
+        -> Result<String, Error> {
+        // The transcription reads the graphemes of the EPA
+        let options = EpaOptions {
+            vaf: Vaf::Cedilla,
+            vvf: Vvf::H,
+            normalization: Normalization::Nfc,
+            ..options.clone()
+        };
+        let output = self.transliterate(input, &options)?;
+
+        Ok(ipa::transcribe(&output, ipa_options))
+    }
+
+    /// Transliterates keeping what every rule did to the text, that starts
+    /// composed (NFC)
+    pub fn trace(&self, input: &str, options: &EpaOptions) -> Result<Vec<Step>, Error> {
+        let normalized = normalize::nfc(input);
+        let mut steps: Vec<Step> = vec![];
+        let mut marks = Marks::of(&normalized.text, options);
+        for rule in self.rules.iter() {
+            let text = steps.last().map_or_else(|| normalized.text.to_string(), |s| s.output.clone());
+            let step = step(rule.as_ref(), text, options).map_err(|e| normalized.error(e, input))?;
+            marks = marks.follow(&step.input, &step.matches);
+            steps.push(step);
+        }
+
+        let vaf_output = VafOutput(marks);
+        let rules = Some(&vaf_output as &dyn PipelineRule).into_iter()
+            .chain(Some(&NfdOutput as &dyn PipelineRule).filter(|_| options.normalization == Normalization::Nfd));
+        for rule in rules {
+            let text = steps.last().map_or_else(|| normalized.text.to_string(), |s| s.output.clone());
+            steps.push(step(rule, text, options)?);
+        }
+
+        Ok(steps)
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+// Runs a rule keeping what it did to the text
+fn step(rule: &dyn PipelineRule, input: String, options: &EpaOptions) -> Result<Step, Error> {
+    let mut matches = vec![];
+    let output = run_rule(rule, &input, options, Some(&mut matches))?;
+
+    Ok(Step { name: rule.name().to_string(), input, output, matches })
+}
+
+// Runs of blanks and of the rest of characters, the words the rules see
+pub(crate) fn tokens(input: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    let mut blank = None;
+
+    for (i, c) in input.char_indices() {
+        let is_blank = c.is_whitespace();
+        if blank.is_some_and(|b| b != is_blank) {
+            tokens.push(&input[start..i]);
+            start = i;
+        }
+        blank = Some(is_blank);
+    }
+
+    if start < input.len() {
+        tokens.push(&input[start..]);
+    }
+
+    tokens
+}
+
+// Runs a rule following the marks of the graphemes the last step writes,
+// when the options need them
+fn run_marked(rule: &dyn PipelineRule, input: &str, marks: &Marks, options: &EpaOptions)
+    -> Result<(String, Marks), Error> {
+    if !Marks::needed(options) {
+        return Ok((run_rule(rule, input, options, None)?, Marks::default()));
+    }
+
+    let mut matches = vec![];
+    let output = run_rule(rule, input, options, Some(&mut matches))?;
+    Ok((output, marks.follow(input, &matches)))
+}
+
+// Runs the rules over a word alone, failing with the index of the rule
+fn run_word(word: &str, marks: Marks, options: &EpaOptions, rules: &[Arc<dyn PipelineRule>])
+    -> Result<(String, Marks), (usize, Error)> {
+    let mut output = (word.to_string(), marks);
+    for (i, rule) in rules.iter().enumerate() {
+        output = run_marked(rule.as_ref(), &output.0, &output.1, options).map_err(|e| (i, e))?;
+    }
+
+    Ok(output)
+}
+
+// Runs the rules once over every distinct word of the input. The error is
+// the one of the first rule a word fails, as the whole text fails there too.
+fn by_words(input: &str, marks: &Marks, options: &EpaOptions, rules: &[Arc<dyn PipelineRule>])
+    -> Result<(String, Marks), Error> {
+    let mut output = String::with_capacity(input.len());
+    let mut out_marks = Marks::default();
+    let mut words: HashMap<(&str, Marks), (String, Marks)> = HashMap::new();
+    let mut error: Option<(usize, Error)> = None;
+    let mut start = 0;
+
+    for token in tokens(input) {
+        let range = start..start + token.len();
+        start = range.end;
+        if token.starts_with(char::is_whitespace) {
+            output += token;
+            continue;
+        }
+
+        let (word, word_marks) = match words.entry((token, marks.within(&range))) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match run_word(token, entry.key().1.clone(), options, rules) {
+                Ok(word) => entry.insert(word),
+                Err((i, e)) => {
+                    if error.as_ref().is_none_or(|(first, _)| i < *first) {
+                        error = Some((i, e.in_word(input, range)));
+                    }
+                    continue;
+                },
+            },
+        };
+
+        out_marks.extend(word_marks, output.len());
+        output += word;
+    }
+
+    match error {
+        Some((_, e)) => Err(e),
+        None => Ok((output, out_marks)),
+    }
+}
+
+// The last step, that isn't part of the pipeline so it can't be removed,
+// with the marks of the graphemes it keeps
+struct VafOutput(Marks);
+
+impl PipelineRule for VafOutput {
+    fn name(&self) -> &str {
+        "vaf_output"
+    }
+
+    fn apply(&self, input: &str, options: &EpaOptions) -> Result<String, Error> {
+        vaf_output(input, options, None, &self.0)
+    }
+
+    fn apply_traced(&self, input: &str, options: &EpaOptions, trace: Option<&mut Vec<Match>>)
+        -> Result<String, Error> {
+        vaf_output(input, options, trace, &self.0)
+    }
+}
+
+// The output decomposed, only in the trace when the options ask for it
+struct NfdOutput;
+
+impl PipelineRule for NfdOutput {
+    fn name(&self) -> &str {
+        "nfd_output"
+    }
+
+    fn apply(&self, input: &str, _: &EpaOptions) -> Result<String, Error> {
+        Ok(normalize::output(input, Normalization::Nfd, None))
+    }
+
+    fn apply_traced(&self, input: &str, _: &EpaOptions, trace: Option<&mut Vec<Match>>)
+        -> Result<String, Error> {
+        Ok(normalize::output(input, Normalization::Nfd, trace))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::CORPUS;
+    use crate::{epa, Dictionary, Vaf, Vvf};
+
+    struct Rehilamiento;
+
+    impl PipelineRule for Rehilamiento {
+        fn name(&self) -> &str {
+            "rehilamiento"
+        }
+
+        fn apply(&self, input: &str, _: &EpaOptions) -> Result<String, Error> {
+            Ok(input.replace('y', "ŷ").replace('Y', "Ŷ"))
+        }
+
+        fn exception(&self, word: &str, _: &EpaOptions) -> Option<String> {
+            match word {
+                "y" => Some("i".to_string()),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_by_words() {
+        let dictionary: Dictionary = vec![("sevilla", "Çebiya")].into_iter().collect();
+        let test_options = vec![
+            EpaOptions::default(),
+            EpaOptions { vaf: Vaf::Z, vvf: Vvf::J, ..Default::default() },
+            EpaOptions { escape_links: true, transliterate_hashtags: true, ..Default::default() },
+            EpaOptions { exceptions: dictionary, ..Default::default() },
+        ];
+
+        // The errors too are the same
+        let pipeline = Pipeline::epa();
+        for options in test_options {
+            for input in CORPUS {
+                let by_words = pipeline.transliterate_composed(input, &options);
+                let by_steps = pipeline.transliterate_by_steps(input, &options);
+                assert_eq!(format!("{:?}", by_words), format!("{:?}", by_steps), "{:?}", input);
+            }
+        }
+
+        assert_eq!(tokens(" a\n\tbc d "), vec![" ", "a", "\n\t", "bc", " ", "d", " "]);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let input = "Yo y la valla";
+        let options = EpaOptions::default();
+
+        let pipeline = Pipeline::epa();
+        assert_eq!(pipeline.transliterate(input, &options).unwrap(), epa(input).unwrap());
+
+        let mut pipeline = Pipeline::epa();
+        pipeline.insert_after("ll_rule", Rehilamiento).expect("Wrong rule");
+        assert_eq!(pipeline.transliterate(input, &options).unwrap(), "Ŷo i la baŷa");
+
+        let steps = pipeline.trace(input, &options).expect("Wrong parser");
+        assert_eq!(steps[6].name, "rehilamiento");
+        assert_eq!(steps[6].matches, vec![
+            Match { rule: None, span: 0..3, output: "Ŷo ".to_string() },
+            Match { rule: None, span: 3..4, output: "i".to_string() },
+            Match { rule: None, span: 4..12, output: " la baŷa".to_string() },
+        ]);
+
+        pipeline.remove("ll_rule").expect("Wrong rule");
+        assert_eq!(pipeline.transliterate(input, &options).unwrap(), "Ŷo i la bâlla");
+
+        let mut names: Vec<String> = pipeline.names().map(String::from).collect();
+        names.swap(0, 5);
+        pipeline.reorder(&names).expect("Wrong rule");
+        assert_eq!(pipeline.names().next(), Some("rehilamiento"));
+        assert_eq!(pipeline.transliterate(input, &options).unwrap(), "Ŷo i la bâlla");
+
+        assert!(pipeline.remove("ll_rule").is_err());
+        assert!(pipeline.push(Rehilamiento).is_err());
+        assert!(pipeline.reorder(&["h_rule"]).is_err());
+    }
+}
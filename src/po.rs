@@ -0,0 +1,264 @@
+// Gettext PO catalogues, where the rules only see the translations, the
+// msgstr strings, and their placeholders, the msgid strings, the comments and
+// the flags are written back as they are.
+
+use std::ops::Range;
+
+use crate::markup::{self, Text, Writer};
+use crate::pipeline::Pipeline;
+use crate::{EpaOptions, Error};
+
+/// What the PO mode writes besides the translations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoOptions {
+    /// Mark the entries with a translation as `fuzzy`, so a translator
+    /// reviews them
+    pub fuzzy: bool,
+}
+
+fn is_string(line: &str) -> bool {
+    line.trim_start().starts_with('"')
+}
+
+fn is_keyword(line: &str, keyword: &str) -> bool {
+    line.strip_prefix(keyword).is_some_and(|rest| rest.starts_with([' ', '\t', '[', '"']))
+}
+
+// Lines of every entry, that start after a blank line or after the strings of
+// the entry before
+fn entries(input: &str) -> Vec<Vec<Range<usize>>> {
+    let mut entries = vec![];
+    let mut entry: Vec<Range<usize>> = vec![];
+    let mut translated = false;
+
+    for line in markup::lines(input) {
+        let text = &input[line.clone()];
+        if text.trim().is_empty() || (translated && !is_string(text)) {
+            if !entry.is_empty() {
+                entries.push(std::mem::take(&mut entry));
+            }
+            translated = false;
+        }
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        translated = translated || is_keyword(text, "msgstr");
+        entry.push(line);
+    }
+    if !entry.is_empty() {
+        entries.push(entry);
+    }
+
+    entries
+}
+
+// Length of the printf, {named} or tag placeholder at the start of text
+pub(crate) fn placeholder(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    match bytes.first() {
+        // %{name} of Ruby
+        Some(b'%') if text[1..].starts_with('{') => text.find('}').map(|e| e + 1),
+        Some(b'%') => {
+            let mut i = 1;
+            // %(name)s of Python
+            if bytes.get(i) == Some(&b'(') {
+                i = text.find(')')? + 1;
+            }
+            i += bytes[i..].iter().take_while(|b| b.is_ascii_digit() || b"$-+ #0'.*".contains(b)).count();
+            i += bytes[i..].iter().take_while(|b| b"hlLqjzt".contains(b)).count();
+            match bytes.get(i) {
+                Some(c) if b"diouxXeEfFgGaAcspn%".contains(c) => Some(i + 1),
+                _ => None,
+            }
+        },
+        Some(b'{') if !text.starts_with("{{") => {
+            let end = text.find(['}', '{', ' ', '"'])?;
+            match bytes[end] {
+                b'}' => Some(end + 1),
+                _ => None,
+            }
+        },
+        Some(b'<') if bytes.get(1).is_some_and(|b| b.is_ascii_alphabetic() || *b == b'/') => {
+            text.find('>').map(|e| e + 1)
+        },
+        _ => None,
+    }
+}
+
+// Adds the content of a string, where escapes are decoded and placeholders
+// are markup
+fn push_string(text: &mut Text, input: &str, range: Range<usize>) {
+    let mut start = range.start;
+    let mut i = range.start;
+
+    while i < range.end {
+        let rest = &input[i..range.end];
+        if rest.starts_with('\\') && rest.len() > 1 {
+            let decoded = match rest.as_bytes()[1] {
+                b'n' => "\n",
+                b't' => "\t",
+                b'r' => "\r",
+                b'"' => "\"",
+                b'\\' => "\\",
+                // An unknown escape is text, up to the end of its character
+                _ => {
+                    i += '\\'.len_utf8() + rest[1..].chars().next().map_or(1, char::len_utf8);
+                    continue;
+                },
+            };
+            text.push(input, start..i);
+            text.push_decoded(decoded, i..i + 2);
+            i += 2;
+            start = i;
+            continue;
+        }
+
+        match placeholder(rest) {
+            Some(len) => {
+                text.push(input, start..i);
+                text.push_markup(i..i + len);
+                i += len;
+                start = i;
+            },
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    text.push(input, start..range.end);
+}
+
+// Text of a msgstr and its continuation lines
+fn translation(input: &str, lines: &[Range<usize>]) -> Text {
+    let mut text = Text::new();
+
+    for line in lines {
+        let open = match input[line.clone()].find('"') {
+            Some(q) => line.start + q + 1,
+            None => {
+                text.push_markup(line.clone());
+                continue;
+            },
+        };
+
+        // The string ends at the first quote that isn't escaped
+        let bytes = input.as_bytes();
+        let mut close = open;
+        while close < line.end && bytes[close] != b'"' {
+            close += if bytes[close] == b'\\' { 2 } else { 1 };
+        }
+        let close = close.min(line.end);
+
+        text.push_markup(line.start..open);
+        push_string(&mut text, input, open..close);
+        text.push_markup(close..line.end);
+    }
+
+    text
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// Marks an entry as fuzzy, in its flags line or in a new one before the
+// previous msgid or the keywords
+fn mark_fuzzy(writer: &mut Writer, input: &str, entry: &[Range<usize>]) {
+    if let Some(flags) = entry.iter().find(|l| input[(*l).clone()].starts_with("#,")) {
+        let line = &input[flags.clone()];
+        if !line[2..].split(',').any(|flag| flag.trim() == "fuzzy") {
+            writer.insert(flags.start + line.trim_end().len(), ", fuzzy");
+        }
+        return;
+    }
+
+    let before = entry.iter()
+        .find(|l| input[(*l).clone()].starts_with("#|") || !input[(*l).clone()].starts_with('#'))
+        .unwrap_or(&entry[0]);
+    let newline = match input[before.clone()].ends_with("\r\n") {
+        true => "\r\n",
+        false => "\n",
+    };
+    writer.insert(before.start, &format!("#, fuzzy{}", newline));
+}
+
+pub(crate) fn transliterate(pipeline: &Pipeline, input: &str, options: &EpaOptions, po_options: &PoOptions)
+    -> Result<String, Error> {
+    let mut writer = Writer::new(pipeline, input, options);
+
+    for entry in entries(input) {
+        let text = |i: usize| &input[entry[i].clone()];
+        let strings = |i: usize| entry[i + 1..].iter().take_while(|l| is_string(&input[(*l).clone()])).count();
+
+        // The header is the entry with an empty msgid and no context
+        let msgid = (0..entry.len()).find(|i| is_keyword(text(*i), "msgid"));
+        let header = msgid.is_some_and(|i| text(i).trim_end().ends_with("\"\"") && strings(i) == 0)
+            && !entry.iter().any(|l| is_keyword(&input[l.clone()], "msgctxt"));
+        if header {
+            continue;
+        }
+
+        let translations: Vec<Text> = (0..entry.len())
+            .filter(|i| is_keyword(text(*i), "msgstr"))
+            .map(|i| translation(input, &entry[i..i + 1 + strings(i)]))
+            .collect();
+        if translations.is_empty() {
+            continue;
+        }
+
+        if po_options.fuzzy && translations.iter().any(|t| !t.is_blank()) {
+            mark_fuzzy(&mut writer, input, &entry);
+        }
+        for translation in translations.iter() {
+            writer.write(translation, escape)?;
+        }
+    }
+
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{epa_po, EpaOptions, PoOptions};
+
+    #[test]
+    fn test_po() {
+        let input = "msgid \"\"\nmsgstr \"\"\n\"Language: es\\n\"\n\n\
+                     #: src/main.c:10\n#, c-format\nmsgid \"Hello %s, the {name} iron\"\n\
+                     msgstr \"Hola %s, el hierro de {name}\"\n\n\
+                     #. Plural\nmsgid \"%d shoe\"\nmsgid_plural \"%d shoes\"\nmsgstr[0] \"%d zapato\"\n\
+                     msgstr[1] \"\"\n\"%d zapatos \\\"de Zaragoza\\\"\\n\"\n\"y Jerez\"\n\n\
+                     msgid \"Untranslated\"\nmsgstr \"\"\n\n\
+                     #~ msgid \"Old\"\n#~ msgstr \"Hierro\"\n";
+
+        let expected = "msgid \"\"\nmsgstr \"\"\n\"Language: es\\n\"\n\n\
+                        #: src/main.c:10\n#, c-format\nmsgid \"Hello %s, the {name} iron\"\n\
+                        msgstr \"Ola %s, el ierro de {name}\"\n\n\
+                        #. Plural\nmsgid \"%d shoe\"\nmsgid_plural \"%d shoes\"\nmsgstr[0] \"%d çapato\"\n\
+                        msgstr[1] \"\"\n\"%d çapatô \\\"de Çaragoça\\\"\\n\"\n\"y Herêh\"\n\n\
+                        msgid \"Untranslated\"\nmsgstr \"\"\n\n\
+                        #~ msgid \"Old\"\n#~ msgstr \"Hierro\"\n";
+        assert_eq!(epa_po(input, &EpaOptions::default(), &PoOptions::default()).expect("Wrong parser"), expected);
+
+        let output = epa_po(input, &EpaOptions::default(), &PoOptions { fuzzy: true }).expect("Wrong parser");
+        assert!(output.contains("#: src/main.c:10\n#, c-format, fuzzy\nmsgid"));
+        assert!(output.contains("#. Plural\n#, fuzzy\nmsgid \"%d shoe\""));
+        assert!(output.contains("\n\nmsgid \"Untranslated\""));
+        assert!(output.starts_with("msgid \"\"\nmsgstr \"\""));
+
+        let output = epa_po("msgid \"a\"\nmsgstr \"hola \\ñ hierro\"\n", &EpaOptions::default(), &PoOptions::default());
+        assert_eq!(output.expect("Wrong parser"), "msgid \"a\"\nmsgstr \"ola \\ñ ierro\"\n");
+    }
+}
@@ -0,0 +1,231 @@
+// Spans of the input the rules must not see, either because they are whole
+// words with a known replacement or because they must be kept as they are.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use pest::Parser;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{escape, keep_case, AndaluhParser, Dictionary, EpaOptions, Error, Match, Rule, Warning};
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Span<'a> {
+    // Text that goes through the rules
+    Text(&'a str),
+    // Text that reaches the output as it is
+    Kept(&'a str),
+    // A whole word and its replacement from an exception table
+    Exception(&'a str, String),
+}
+
+// Splits the input in the spans a rule must transliterate and the ones it
+// must keep: links when they are escaped, words the grammar can't parse in
+// the lenient mode, the words of its exception map and the ones of the user
+// dictionary.
+pub(crate) fn spans<'a>(input: &'a str, options: &EpaOptions, map: Option<&HashMap<&str, &str>>)
+    -> Vec<Span<'a>> {
+    match map {
+        Some(m) => spans_with(input, options, |word| m.get(word).map(|w| w.to_string())),
+        None => spans_with(input, options, |_| None),
+    }
+}
+
+// Like spans, finding the exceptions with lookup, that gets lowercase words.
+pub(crate) fn spans_with<'a, F>(input: &'a str, options: &EpaOptions, lookup: F) -> Vec<Span<'a>>
+    where F: Fn(&str) -> Option<String> {
+    let spans = match options.escape_links {
+        true => escape::escape_links(input, options.transliterate_hashtags),
+        false => vec![Span::Text(input)],
+    };
+
+    let spans = match options.lenient {
+        true => spans.into_iter().flat_map(|span| match span {
+            Span::Text(text) => parsable(text),
+            span => vec![span],
+        }).collect(),
+        false => spans,
+    };
+
+    spans.into_iter()
+        .flat_map(|span| match span {
+            Span::Text(text) => exceptions(text, &lookup, &options.exceptions),
+            span => vec![span],
+        }).collect()
+}
+
+// Words with a character the grammar can't parse, with a warning for each
+pub(crate) fn unparsable(input: &str) -> Vec<(Range<usize>, Warning)> {
+    let pairs = match AndaluhParser::parse(Rule::lenient, input) {
+        Ok(pairs) => pairs,
+        // Every character either is or is not ok, it can't fail
+        Err(_) => return vec![],
+    };
+
+    pairs.filter(|pair| pair.as_rule() == Rule::unparsable)
+        .map(|pair| {
+            let span = pair.as_span().start()..pair.as_span().end();
+            let bad = pair.into_inner().next()
+                .and_then(|c| c.as_str().chars().next())
+                .unwrap_or('\u{FFFD}');
+            let warning = Warning {
+                span: span.clone(),
+                message: format!("unsupported character U+{:04X}, text kept as is", bad as u32),
+            };
+            (span, warning)
+        }).collect()
+}
+
+// Words the lenient mode keeps, outside the links the options escape, with a
+// warning for each
+pub(crate) fn kept(input: &str, options: &EpaOptions) -> Vec<(Range<usize>, Warning)> {
+    let spans = match options.escape_links {
+        true => escape::escape_links(input, options.transliterate_hashtags),
+        false => vec![Span::Text(input)],
+    };
+
+    let mut kept = vec![];
+    let mut offset = 0;
+    for span in spans {
+        match span {
+            Span::Text(text) => {
+                kept.extend(unparsable(text).into_iter().map(|(span, warning)| {
+                    let span = span.start + offset..span.end + offset;
+                    (span.clone(), Warning { span, ..warning })
+                }));
+                offset += text.len();
+            },
+            Span::Kept(text) | Span::Exception(text, _) => offset += text.len(),
+        }
+    }
+
+    kept
+}
+
+fn parsable(input: &str) -> Vec<Span<'_>> {
+    let mut spans = vec![];
+    let mut start = 0;
+
+    for (span, _) in unparsable(input) {
+        if span.start > start {
+            spans.push(Span::Text(&input[start..span.start]));
+        }
+        spans.push(Span::Kept(&input[span.clone()]));
+        start = span.end;
+    }
+
+    if start < input.len() {
+        spans.push(Span::Text(&input[start..]));
+    }
+
+    spans
+}
+
+// Protects every whole word of the input with an exception, already replaced
+// keeping its case, and keeps the words of the user dictionary.
+fn exceptions<'a, F>(input: &'a str, lookup: &F, dictionary: &Dictionary) -> Vec<Span<'a>>
+    where F: Fn(&str) -> Option<String> {
+    let mut spans = vec![];
+    let mut start = 0;
+
+    for (i, word) in input.split_word_bound_indices() {
+        let lword = word.to_lowercase();
+        let span = match lookup(&lword) {
+            Some(repl) => Span::Exception(word, keep_case(&repl, word)),
+            None if dictionary.get(&lword).is_some() => Span::Kept(word),
+            None => continue,
+        };
+
+        if i > start {
+            spans.push(Span::Text(&input[start..i]));
+        }
+        spans.push(span);
+        start = i + word.len();
+    }
+
+    if start < input.len() {
+        spans.push(Span::Text(&input[start..]));
+    }
+
+    spans
+}
+
+// Runs rules over the text spans and joins them back with the other ones,
+// recording in trace the pieces that changed.
+pub(crate) fn transliterate<F>(spans: Vec<Span>, trace: Option<&mut Vec<Match>>, mut rules: F)
+    -> Result<String, Error>
+    where F: FnMut(&str, Option<&mut Vec<Match>>) -> Result<String, Error> {
+    transliterate_at(spans, trace, |text, _, trace| rules(text, trace))
+}
+
+// Like transliterate, giving the rules the offset of every text span too.
+pub(crate) fn transliterate_at<F>(spans: Vec<Span>, mut trace: Option<&mut Vec<Match>>, mut rules: F)
+    -> Result<String, Error>
+    where F: FnMut(&str, usize, Option<&mut Vec<Match>>) -> Result<String, Error> {
+    let mut output = String::new();
+    let mut offset = 0;
+
+    for (i, span) in spans.iter().enumerate() {
+        match span {
+            Span::Text(text) => {
+                let mut matches = vec![];
+                let recorder = trace.as_ref().map(|_| &mut matches);
+                output += &rules(text, offset, recorder).map_err(|e| e.after(&source(&spans[..i])))?;
+
+                if let Some(t) = trace.as_deref_mut() {
+                    t.extend(matches.into_iter().map(|m| Match {
+                        span: m.span.start + offset..m.span.end + offset,
+                        ..m
+                    }));
+                }
+                offset += text.len();
+            },
+            Span::Kept(text) => {
+                output += text;
+                offset += text.len();
+            },
+            Span::Exception(word, repl) => {
+                output += repl;
+                if let Some(t) = trace.as_deref_mut() {
+                    let span = offset..offset + word.len();
+                    t.push(Match { rule: None, span, output: repl.to_string() });
+                }
+                offset += word.len();
+            },
+        }
+    }
+
+    Ok(output)
+}
+
+fn source(spans: &[Span]) -> String {
+    spans.iter()
+        .map(|span| match span {
+            Span::Text(text) | Span::Kept(text) | Span::Exception(text, _) => *text,
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::hashmap;
+
+    #[test]
+    fn test_exceptions() {
+        let map = hashmap!{ "os" => "ô", "gin" => "yin" };
+        let dictionary: Dictionary = vec![("sevilla", "Çebiya")].into_iter().collect();
+        let input = "todos os Gin, AND_0 en Sevilla";
+        let expected = vec![
+            Span::Text("todos "),
+            Span::Exception("os", "ô".to_string()),
+            Span::Text(" "),
+            Span::Exception("Gin", "Yin".to_string()),
+            Span::Text(", AND_0 en "),
+            Span::Kept("Sevilla"),
+        ];
+
+        let lookup = |word: &str| map.get(word).map(|w| w.to_string());
+        assert_eq!(exceptions(input, &lookup, &dictionary), expected);
+    }
+}
@@ -0,0 +1,459 @@
+// Locale files of web apps, where the rules only see the string values, and
+// the keys, the ICU MessageFormat syntax, the placeholders and the character
+// references are written back as they are, in the same order.
+
+use std::ops::Range;
+
+use crate::markup::{self, Text, Writer};
+use crate::pipeline::Pipeline;
+use crate::{po, EpaOptions, Error};
+
+/// Format of an i18n resource file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    Json,
+    /// YAML, with plain, quoted and block scalars, and flow collections
+    Yaml,
+}
+
+// ICU arguments with messages inside, like {count, plural, one {...} other {...}}
+const BRANCHED: &[&str] = &["plural", "select", "selectordinal"];
+// Plain YAML scalars that aren't strings
+const KEYWORDS: &[&str] = &["true", "false", "yes", "no", "on", "off", "null", "~"];
+
+// How a string is quoted, which tells its escapes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quote {
+    // JSON strings and double-quoted YAML, with backslash escapes
+    Double,
+    // Single-quoted YAML, where '' is a quote
+    Single,
+    Plain,
+}
+
+// Character of the backslash escape at the start of text, or nothing for a
+// \u escape of no character, and its length
+fn unescape(text: &str) -> Option<(String, usize)> {
+    let hex = |from: usize| text.get(from..from + 4).and_then(|h| u32::from_str_radix(h, 16).ok());
+    let c = match text.as_bytes().get(1)? {
+        b'n' => '\n',
+        b't' => '\t',
+        b'r' => '\r',
+        b'b' => '\u{8}',
+        b'f' => '\u{c}',
+        b'"' => '"',
+        b'\\' => '\\',
+        b'/' => '/',
+        b'u' => {
+            let code = match hex(2) {
+                Some(code) => code,
+                None => return Some((String::new(), 2)),
+            };
+            // A surrogate pair
+            if (0xd800..0xdc00).contains(&code) {
+                let low = hex(8).filter(|l| text[6..].starts_with("\\u") && (0xdc00..0xe000).contains(l));
+                let pair = low.and_then(|low| char::from_u32(0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00)));
+                if let Some(c) = pair {
+                    return Some((c.to_string(), 12));
+                }
+            }
+            return Some((char::from_u32(code).map(String::from).unwrap_or_default(), 6));
+        },
+        _ => return None,
+    };
+
+    Some((c.to_string(), 2))
+}
+
+// Length of the ICU argument at the start of text, or of its start up to the
+// brace of its first message, with its type when it has messages
+fn argument(text: &str) -> Option<(usize, Option<&str>)> {
+    let end = text[1..].find(['{', '}'])? + 1;
+    let parts: Vec<&str> = text[1..end].split(',').map(str::trim).collect();
+    if parts[0].is_empty() || !parts[0].chars().all(|c| c.is_alphanumeric() || "_.-".contains(c)) {
+        return None;
+    }
+
+    match text.as_bytes()[end] {
+        b'}' => Some((end + 1, None)),
+        _ => parts.get(1).filter(|kind| BRANCHED.contains(kind)).map(|kind| (end + 1, Some(*kind))),
+    }
+}
+
+// Length of the end of a message of an ICU argument at the start of text,
+// with the selector and the brace of the next one, and whether it ends the
+// argument instead
+fn selector(text: &str) -> (usize, bool) {
+    let spaces = |from: usize| from + text[from..].len() - text[from..].trim_start().len();
+    let i = spaces(1);
+    if text[i..].starts_with('}') {
+        return (i + 1, true);
+    }
+
+    let i = spaces(i + text[i..].find(|c: char| c.is_whitespace() || c == '{' || c == '}').unwrap_or(text.len() - i));
+    match text[i..].starts_with('{') {
+        true => (i + 1, false),
+        false => (1, true),
+    }
+}
+
+// Text of a string, where escapes and character references are decoded, and
+// placeholders and the syntax of ICU arguments are markup
+fn message(input: &str, range: Range<usize>, quote: Quote) -> Text {
+    let mut text = Text::new();
+    let mut start = range.start;
+    let mut i = range.start;
+    // Types of the ICU arguments whose messages the text is in
+    let mut open: Vec<&str> = vec![];
+
+    while i < range.end {
+        let rest = &input[i..range.end];
+        let decoded = match rest.as_bytes()[0] {
+            b'\\' if quote == Quote::Double => unescape(rest),
+            b'\'' if quote == Quote::Single && rest.starts_with("''") => Some(("'".to_string(), 2)),
+            b'&' => markup::reference(input, i, range.end).map(|(c, end)| (c, end - i)),
+            _ => None,
+        };
+        if let Some((decoded, len)) = decoded {
+            text.push(input, start..i);
+            text.push_decoded(&decoded, i..i + len);
+            i += len;
+            start = i;
+            continue;
+        }
+
+        let len = match rest.as_bytes()[0] {
+            // {{name}} of i18next and Handlebars
+            b'{' if rest.starts_with("{{") => rest.find("}}").map(|e| e + 2),
+            b'{' => argument(rest).map(|(len, kind)| {
+                open.extend(kind);
+                len
+            }),
+            b'}' if !open.is_empty() => {
+                let (len, end) = selector(rest);
+                if end {
+                    open.pop();
+                }
+                Some(len)
+            },
+            b'#' if open.last().is_some_and(|kind| *kind != "select") => Some(1),
+            // $t(key) of i18next
+            b'$' if rest.starts_with("$t(") => rest.find(')').map(|e| e + 1),
+            b'%' | b'<' => po::placeholder(rest),
+            _ => None,
+        };
+
+        match len {
+            Some(len) => {
+                text.push(input, start..i);
+                text.push_markup(i..i + len);
+                i += len;
+                start = i;
+            },
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    text.push(input, start..range.end);
+    text
+}
+
+fn escape(text: &str, quote: Quote) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match (quote, c) {
+            (Quote::Double, '\\') => escaped.push_str("\\\\"),
+            (Quote::Double, '"') => escaped.push_str("\\\""),
+            (Quote::Double, '\n') => escaped.push_str("\\n"),
+            (Quote::Double, '\t') => escaped.push_str("\\t"),
+            (Quote::Double, '\r') => escaped.push_str("\\r"),
+            (Quote::Double, c) if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            (Quote::Single, '\'') => escaped.push_str("''"),
+            (_, c) => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// End of the string whose content starts at start, at its closing quote
+fn string_end(input: &str, start: usize, quote: u8) -> usize {
+    let bytes = input.as_bytes();
+    let mut i = start;
+    while i < bytes.len() && bytes[i] != quote {
+        i += match bytes[i] {
+            b'\\' if quote == b'"' => 2,
+            _ => 1,
+        };
+    }
+    // '' is a quote in single-quoted YAML
+    match quote == b'\'' && bytes.get(i + 1) == Some(&b'\'') {
+        true => string_end(input, i + 2, quote),
+        false => i.min(bytes.len()),
+    }
+}
+
+// Contents of the strings of a JSON document that aren't keys
+fn json(input: &str) -> Vec<(Range<usize>, Quote)> {
+    let bytes = input.as_bytes();
+    let mut values = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'"' {
+            i += 1;
+            continue;
+        }
+
+        let end = string_end(input, i + 1, b'"');
+        let next = input[(end + 1).min(input.len())..].trim_start();
+        if !next.starts_with(':') {
+            values.push((i + 1..end, Quote::Double));
+        }
+        i = end + 1;
+    }
+
+    values
+}
+
+// Where the key at the start of a YAML line ends, after its colon
+fn key(input: &str, start: usize, end: usize) -> Option<usize> {
+    let colon = match input.as_bytes().get(start) {
+        Some(q @ (b'"' | b'\'')) => {
+            let close = (string_end(input, start + 1, *q) + 1).min(end);
+            close + input[close..end].find(':').filter(|c| input[close..close + c].trim().is_empty())?
+        },
+        _ => {
+            let line = &input[start..end];
+            line.find(": ").or_else(|| line.ends_with(':').then(|| line.len() - 1)).map(|c| start + c)?
+        },
+    };
+
+    match input[colon + 1..end].starts_with([' ', '\t']) || colon + 1 == end {
+        true => Some(colon + 1),
+        false => None,
+    }
+}
+
+// Whether a plain YAML scalar is a number, in the forms of YAML, like 0x1f,
+// -2.5e3 or .inf, and not the ones of Rust, like inf or NaN
+fn is_number(value: &str) -> bool {
+    let digits = |text: &str, radix: u32| !text.is_empty() && text.chars().all(|c| c.is_digit(radix));
+    if let Some(hex) = value.strip_prefix("0x") {
+        return digits(hex, 16);
+    }
+    if let Some(octal) = value.strip_prefix("0o") {
+        return digits(octal, 8);
+    }
+
+    let unsigned = value.strip_prefix(['-', '+']).unwrap_or(value);
+    if [".inf", ".Inf", ".INF"].contains(&unsigned) || [".nan", ".NaN", ".NAN"].contains(&value) {
+        return true;
+    }
+
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(e) => (&unsigned[..e], Some(&unsigned[e + 1..])),
+        None => (unsigned, None),
+    };
+    // Digits with a dot anywhere, like 1.5, .5 or 1.
+    let mantissa = mantissa.matches('.').count() <= 1 && digits(&mantissa.replace('.', ""), 10);
+
+    mantissa && exponent.is_none_or(|e| digits(e.strip_prefix(['-', '+']).unwrap_or(e), 10))
+}
+
+// Whether a plain YAML scalar is a string
+fn is_string(value: &str) -> bool {
+    !KEYWORDS.iter().any(|k| value.eq_ignore_ascii_case(k)) && !is_number(value)
+}
+
+// Contents of the scalars of the YAML flow collection at start, like [a, b]
+// or {c: d}, that aren't keys, and where it ends
+fn flow(input: &str, start: usize) -> (Vec<(Range<usize>, Quote)>, usize) {
+    let bytes = input.as_bytes();
+    let mut values = vec![];
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < bytes.len() {
+        let rest = &input[i..];
+        // Where the scalar or the token at i ends, and its content if it's a
+        // value
+        let (end, value) = match bytes[i] {
+            b'[' | b'{' => {
+                depth += 1;
+                (i + 1, None)
+            },
+            b']' | b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return (values, i + 1);
+                }
+                (i + 1, None)
+            },
+            b',' | b':' | b'?' => (i + 1, None),
+            b'#' => (rest.find('\n').map_or(input.len(), |e| i + e), None),
+            b if b.is_ascii_whitespace() => (i + 1, None),
+            // Anchors, tags and aliases
+            b'&' | b'!' | b'*' => {
+                let len = rest.find(|c: char| c.is_whitespace() || ",[]{}".contains(c)).unwrap_or(rest.len());
+                (i + len, None)
+            },
+            q @ (b'"' | b'\'') => {
+                let close = string_end(input, i + 1, q);
+                let quote = if q == b'"' { Quote::Double } else { Quote::Single };
+                ((close + 1).min(input.len()), Some((i + 1..close, quote)))
+            },
+            _ => {
+                let mut len = rest.find(|c: char| c == '\n' || ",[]{}".contains(c)).unwrap_or(rest.len());
+                // A colon before a blank ends a key, and a # after one a comment
+                len = [": ", ":\t", ":\n", " #"].iter().filter_map(|m| rest[..len].find(m)).fold(len, usize::min);
+                if rest[..len].ends_with(':') {
+                    len -= 1;
+                }
+                let value = rest[..len].trim_end();
+                (i + len, is_string(value).then(|| (i..i + value.len(), Quote::Plain)))
+            },
+        };
+
+        // Keys are followed by a colon
+        let key = input[end..].trim_start_matches([' ', '\t']).starts_with(':');
+        values.extend(value.filter(|_| !key));
+        i = end;
+    }
+
+    (values, input.len())
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+// Contents of the scalars of a YAML document that aren't keys, written on the
+// lines of the values, or in block scalars
+fn yaml(input: &str) -> Vec<(Range<usize>, Quote)> {
+    let lines = markup::lines(input);
+    let mut values = vec![];
+    let mut l = 0;
+
+    while l < lines.len() {
+        let line = &input[lines[l].clone()];
+        let end = lines[l].start + line.trim_end_matches(['\r', '\n']).len();
+        let mut i = lines[l].start + indent(line);
+        let spaces = |i: usize| i + input[i..end].len() - input[i..end].trim_start().len();
+        l += 1;
+
+        // Directives and the markers of the documents
+        if ["---", "...", "%"].iter().any(|m| line.starts_with(m)) {
+            continue;
+        }
+
+        // The markers of the sequences, the key and the anchors and tags
+        while input[i..end].starts_with("- ") || input[i..end] == *"-" {
+            i = spaces(i + 1);
+        }
+        if let Some(colon) = key(input, i, end) {
+            i = spaces(colon);
+        }
+        while input[i..end].starts_with(['&', '!']) {
+            i = spaces(input[i..end].find(' ').map_or(end, |s| i + s));
+        }
+
+        match input.as_bytes().get(i).filter(|_| i < end) {
+            None | Some(b'#' | b'*') => {},
+            Some(b'[' | b'{') => {
+                let (mut found, close) = flow(input, i);
+                values.append(&mut found);
+                // Flow collections can go on in the next lines
+                while l < lines.len() && lines[l].start < close {
+                    l += 1;
+                }
+            },
+            Some(b'|' | b'>') => {
+                // The lines more indented than the key
+                let inside = |b: &&Range<usize>| {
+                    let text = &input[(*b).clone()];
+                    text.trim().is_empty() || indent(text) > indent(line)
+                };
+                let block: Vec<&Range<usize>> = lines[l..].iter().take_while(inside).collect();
+                if let Some(last) = block.iter().rposition(|b| !input[(*b).clone()].trim().is_empty()) {
+                    let last_end = block[last].start + input[block[last].clone()].trim_end().len();
+                    values.push((block[0].start..last_end, Quote::Plain));
+                    l += last + 1;
+                }
+            },
+            Some(q @ (b'"' | b'\'')) => {
+                let close = string_end(input, i + 1, *q);
+                values.push((i + 1..close, if *q == b'"' { Quote::Double } else { Quote::Single }));
+                // Quoted scalars can go on in the next lines
+                while l < lines.len() && lines[l].start <= close {
+                    l += 1;
+                }
+            },
+            _ => {
+                let value = input[i..end].find(" #").map_or(&input[i..end], |c| &input[i..i + c]).trim_end();
+                if is_string(value) {
+                    values.push((i..i + value.len(), Quote::Plain));
+                }
+            },
+        }
+    }
+
+    values
+}
+
+pub(crate) fn transliterate(pipeline: &Pipeline, input: &str, options: &EpaOptions, format: Resource)
+    -> Result<String, Error> {
+    let mut writer = Writer::new(pipeline, input, options);
+    let values = match format {
+        Resource::Json => json(input),
+        Resource::Yaml => yaml(input),
+    };
+
+    for (range, quote) in values {
+        writer.write(&message(input, range, quote), |t| escape(t, quote))?;
+    }
+
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{epa_resource, EpaOptions, Resource};
+
+    #[test]
+    fn test_is_number() {
+        for number in ["3", "-2.5e3", "+.5", "1.", "0x1f", "0o17", ".inf", "-.Inf", ".NaN"].iter() {
+            assert!(is_number(number), "{}", number);
+        }
+        for string in ["Inf", "infinity", "NaN", "nan", ".", "e3", "1e", "0x", "1.2.3", "- 1"].iter() {
+            assert!(!is_number(string), "{}", string);
+        }
+    }
+
+    #[test]
+    fn test_resources() {
+        let json = "{\n  \"hierro\": \"El hierro de {name}\",\n  \"zapatos\": {\n    \"count\": \
+                    \"{count, plural, =0 {Ningún zapato} one {# zapato} other {# zapatos de {city}}}\",\n    \
+                    \"list\": [\"Jerez &amp; C\\u00e1diz\", \"<b>{{user}}</b> hizo %s\", 3]\n  }\n}\n";
+        let expected = "{\n  \"hierro\": \"El ierro de {name}\",\n  \"zapatos\": {\n    \"count\": \
+                        \"{count, plural, =0 {Ningún çapato} one {# çapato} other {# çapatô de {city}}}\",\n    \
+                        \"list\": [\"Herêh &amp; Cádî\", \"<b>{{user}}</b> iço %s\", 3]\n  }\n}\n";
+        assert_eq!(epa_resource(json, &EpaOptions::default(), Resource::Json).expect("Wrong parser"), expected);
+
+        // Escapes of no character are kept as they are
+        let json = "[\"Hola \\ud800 Xavi \\udc00Hierro \\uZZ\"]";
+        let expected = "[\"Ola \\ud800 Çabi \\udc00Ierro \\uZZ\"]";
+        assert_eq!(epa_resource(json, &EpaOptions::default(), Resource::Json).expect("Wrong parser"), expected);
+
+        let yaml = "es:\n  # El hierro\n  hierro: El hierro de %{name} # Jerez\n  \
+                    \"zapato\": 'El zapato de ''Jerez'''\n  count: 3\n  list:\n    - \"Zaragoza\\n\"\n    - no\n  \
+                    texto: |\n    El hierro\n\n    de Jerez\n  fin: Jerez\n  \
+                    flow: [Hierro, \"Zapato\", 3, {c: Jerez, 'd': no}]\n  mapa: {a: Hierro,\n    b: [Jerez]}\n";
+        let expected = "es:\n  # El hierro\n  hierro: El ierro de %{name} # Jerez\n  \
+                        \"zapato\": 'Er çapato de ''Herêh'''\n  count: 3\n  list:\n    - \"Çaragoça\\n\"\n    - no\n  \
+                        texto: |\n    El ierro\n\n    de Herêh\n  fin: Herêh\n  \
+                        flow: [Ierro, \"Çapato\", 3, {c: Herêh, 'd': no}]\n  mapa: {a: Ierro,\n    b: [Herêh]}\n";
+        assert_eq!(epa_resource(yaml, &EpaOptions::default(), Resource::Yaml).expect("Wrong parser"), expected);
+    }
+}
@@ -0,0 +1,192 @@
+// Transliteration of texts too big to be read at once, chunk by chunk, always
+// cut at a blank.
+
+use std::io::{self, BufRead, Write};
+
+use crate::pipeline::{tokens, Pipeline};
+use crate::{exceptions, EpaOptions, Error, Warning};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Where the pending text starts in the stream, to report errors from there
+#[derive(Default)]
+struct Position {
+    bytes: usize,
+    lines: usize,
+    columns: usize,
+}
+
+impl Position {
+    fn advance(&mut self, text: &str) {
+        self.bytes += text.len();
+        self.lines += text.matches('\n').count();
+        self.columns = match text.rfind('\n') {
+            Some(i) => text[i + 1..].chars().count(),
+            None => self.columns + text.chars().count(),
+        };
+    }
+}
+
+// Returns the text the lenient mode left untouched
+pub(crate) fn transliterate<R: BufRead, W: Write>(pipeline: &Pipeline, options: &EpaOptions,
+                                                  reader: R, writer: W) -> Result<Vec<Warning>, Error> {
+    // The options may not come from the builder, that checks them too
+    exceptions::check(options)?;
+    transliterate_chunks(pipeline, options, reader, writer, CHUNK_SIZE)
+}
+
+// The last word of every chunk waits for the next one, as rules like ENDING_L
+// need the word after it. It's transliterated with the chunk anyway, so the
+// word before it gets it right, and only the output before it is written.
+fn transliterate_chunks<R: BufRead, W: Write>(pipeline: &Pipeline, options: &EpaOptions,
+                                              mut reader: R, mut writer: W, chunk_size: usize)
+    -> Result<Vec<Warning>, Error> {
+    let mut pending = String::new();
+    let mut position = Position::default();
+    let mut warnings = vec![];
+
+    loop {
+        let eof = read_chunk(&mut reader, &mut pending, chunk_size)?;
+        let (output, chunk_warnings) = match options.lenient {
+            true => pipeline.transliterate_lenient(&pending, options),
+            false => pipeline.transliterate(&pending, options).map(|output| (output, vec![])),
+        }.map_err(|e| e.shifted(position.bytes, position.lines, position.columns))?;
+        let chunk_warnings: Vec<Warning> = chunk_warnings.into_iter()
+            .map(|w| Warning { span: w.span.start + position.bytes..w.span.end + position.bytes, ..w })
+            .collect();
+
+        if eof {
+            writer.write_all(output.as_bytes())?;
+            warnings.extend(chunk_warnings);
+            break;
+        }
+
+        let cut = last_word(&pending);
+        let (written, kept) = match output_cut(&pending[..cut], &output) {
+            Some(out) => (&output[..out], cut),
+            // The rules changed the blanks, so the last word is written as is
+            None => (&output[..], pending.len()),
+        };
+
+        writer.write_all(written.as_bytes())?;
+        position.advance(&pending[..kept]);
+        pending.drain(..kept);
+
+        // The ones of the text that waits come again with the next chunk
+        warnings.extend(chunk_warnings.into_iter().filter(|w| w.span.start < position.bytes));
+    }
+
+    writer.flush()?;
+    Ok(warnings)
+}
+
+// Reads chunk_size bytes and the rest of the word they end in, with the
+// blank after it, so a text without line breaks isn't read at once either,
+// returning whether the reader is over
+fn read_chunk<R: BufRead>(reader: &mut R, pending: &mut String, chunk_size: usize) -> Result<bool, Error> {
+    let mut chunk = vec![];
+    let eof = loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break true;
+        }
+
+        let full = chunk_size.saturating_sub(chunk.len()).min(buffer.len());
+        let (n, blank) = match buffer[full..].iter().position(u8::is_ascii_whitespace) {
+            Some(i) => (full + i + 1, true),
+            None => (buffer.len(), false),
+        };
+        chunk.extend_from_slice(&buffer[..n]);
+        reader.consume(n);
+        if blank {
+            break false;
+        }
+    };
+
+    // An ASCII blank never cuts a character in two
+    let chunk = String::from_utf8(chunk).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    pending.push_str(&chunk);
+    Ok(eof)
+}
+
+// Byte offset of the last word and the blanks after it
+fn last_word(text: &str) -> usize {
+    let tokens = tokens(text);
+    let n = match tokens.last() {
+        Some(t) if t.starts_with(char::is_whitespace) => tokens.len().saturating_sub(2),
+        _ => tokens.len().saturating_sub(1),
+    };
+
+    tokens[..n].iter().map(|t| t.len()).sum()
+}
+
+// Byte offset of the output of source, that has as many runs of blanks
+fn output_cut(source: &str, output: &str) -> Option<usize> {
+    let blanks = tokens(source).iter().filter(|t| t.starts_with(char::is_whitespace)).count();
+    if blanks == 0 {
+        return Some(0);
+    }
+
+    let mut seen = 0;
+    let mut offset = 0;
+    for token in tokens(output) {
+        offset += token.len();
+        if token.starts_with(char::is_whitespace) {
+            seen += 1;
+            if seen == blanks {
+                return Some(offset);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{epa, epa_lenient};
+
+    #[test]
+    fn test_stream() {
+        let input = "Venid todos al\nbalcón, el\n\ngato y el\tperro de Madrid \n\nSevilla\nel";
+        let pipeline = Pipeline::epa();
+        let options = EpaOptions::default();
+
+        for chunk_size in [1, 5, 20, CHUNK_SIZE].iter() {
+            let mut output = vec![];
+            transliterate_chunks(&pipeline, &options, input.as_bytes(), &mut output, *chunk_size)
+                .expect("Wrong parser");
+            assert_eq!(String::from_utf8(output).unwrap(), epa(input).unwrap());
+        }
+
+        // A text without line breaks is read a word at a time past the chunk size
+        let mut pending = String::new();
+        let eof = read_chunk(&mut "Venid todos al balcón".as_bytes(), &mut pending, 7).expect("Wrong reader");
+        assert_eq!((eof, &pending[..]), (false, "Venid todos "));
+
+        // The cut counts blanks, that no exception may add
+        let exceptions = vec![("sevilla", "Çebiya la")].into_iter().collect();
+        let options = EpaOptions { exceptions, ..Default::default() };
+        assert!(transliterate(&pipeline, &options, input.as_bytes(), vec![]).is_err());
+
+        let input = "Venid al\u{0007} balcón, el\ngato\u{0007}";
+        let options = EpaOptions { lenient: true, ..Default::default() };
+        for chunk_size in [1, 5, CHUNK_SIZE].iter() {
+            let mut output = vec![];
+            let warnings = transliterate_chunks(&pipeline, &options, input.as_bytes(), &mut output, *chunk_size)
+                .expect("Wrong parser");
+            assert_eq!((String::from_utf8(output).unwrap(), warnings), epa_lenient(input, &options).unwrap());
+        }
+
+        let options = EpaOptions::default();
+        let input = "Venid todos al\nbalcón, el\ngato\u{0007}";
+        let error = transliterate_chunks(&pipeline, &options, input.as_bytes(), &mut vec![], 1)
+            .expect_err("Wrong parser");
+        match error {
+            Error::Grammar { offset, line, column, .. } => assert_eq!((offset, line, column), (31, 3, 5)),
+            _ => panic!("Wrong error {:?}", error),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Subtitles, where the rules only see the text of the cues, and their
+// numbers, timings and styling tags, and the header and NOTE blocks of
+// WebVTT, are written back as they are.
+
+use std::ops::Range;
+
+use crate::markup::{self, Text, Writer};
+use crate::pipeline::Pipeline;
+use crate::{EpaOptions, Error};
+
+/// Format of a subtitles file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subtitles {
+    /// SubRip, with tags like `<i>` and `{\an8}`
+    Srt,
+    /// WebVTT, with tags like `<v Speaker>` and character references
+    WebVtt,
+}
+
+// Blocks of WebVTT that aren't cues
+const VTT_BLOCKS: &[&str] = &["WEBVTT", "NOTE", "STYLE", "REGION"];
+
+// Lines of every block of the input, split by blank lines
+fn blocks(input: &str) -> Vec<Vec<Range<usize>>> {
+    let mut blocks = vec![];
+    let mut block = vec![];
+
+    for line in markup::lines(input) {
+        match input[line.clone()].trim().is_empty() {
+            true if !block.is_empty() => blocks.push(std::mem::take(&mut block)),
+            true => {},
+            false => block.push(line),
+        }
+    }
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+// Text of a cue, where tags are markup, along with the text of the <lang>
+// tags of other languages
+fn cue(input: &str, range: Range<usize>, format: Subtitles) -> Text {
+    let bytes = input.as_bytes();
+    let mut text = Text::new();
+    let mut start = range.start;
+    let mut i = range.start;
+
+    while i < range.end {
+        let close = match bytes[i] {
+            b'<' => Some('>'),
+            b'{' if bytes.get(i + 1) == Some(&b'\\') => Some('}'),
+            b'&' if format == Subtitles::WebVtt => {
+                if let Some((decoded, end)) = markup::reference(input, i, range.end) {
+                    text.push(input, start..i);
+                    text.push_decoded(&decoded, i..end);
+                    start = end;
+                    i = end;
+                    continue;
+                }
+                None
+            },
+            _ => None,
+        };
+
+        let end = match close.and_then(|c| input[i..range.end].find(c)) {
+            Some(e) => i + e + 1,
+            None => {
+                i += input[i..].chars().next().map_or(1, char::len_utf8);
+                continue;
+            },
+        };
+        let end = match input[i + 1..end - 1].strip_prefix("lang ") {
+            Some(lang) if !markup::is_spanish(lang) => {
+                input[end..range.end].find("</lang>").map_or(range.end, |e| end + e + "</lang>".len())
+            },
+            _ => end,
+        };
+
+        text.push(input, start..i);
+        text.push_markup(i..end);
+        start = end;
+        i = end;
+    }
+
+    text.push(input, start..range.end);
+    text
+}
+
+pub(crate) fn transliterate(pipeline: &Pipeline, input: &str, options: &EpaOptions, format: Subtitles)
+    -> Result<String, Error> {
+    let mut writer = Writer::new(pipeline, input, options);
+
+    for block in blocks(input) {
+        let first = input[block[0].clone()].trim_start_matches('\u{feff}');
+        if format == Subtitles::WebVtt && VTT_BLOCKS.iter().any(|b| first.starts_with(b)) {
+            continue;
+        }
+
+        // The number or the identifier of the cue and its timing come
+        // before the text
+        let text = match block.iter().position(|line| input[line.clone()].contains("-->")) {
+            Some(timing) if timing + 1 < block.len() => block[timing + 1].start..block[block.len() - 1].end,
+            _ => continue,
+        };
+
+        let escape = |t: &str| match format {
+            Subtitles::Srt => t.to_string(),
+            Subtitles::WebVtt => markup::escape(t, None),
+        };
+        writer.write(&cue(input, text, format), escape)?;
+    }
+
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{epa_subtitles, EpaOptions, Subtitles};
+
+    #[test]
+    fn test_subtitles() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:03,500\r\n{\\an8}<i>El hierro</i> de\r\nZaragoza\r\n\r\n\
+                   2\r\n00:00:04,000 --> 00:00:05,000 X1:10 X2:20\r\n<font color=\"#ff0000\">Jerez</font>\r\n";
+        let expected = "1\r\n00:00:01,000 --> 00:00:03,500\r\n{\\an8}<i>El ierro</i> de\r\nÇaragoça\r\n\r\n\
+                        2\r\n00:00:04,000 --> 00:00:05,000 X1:10 X2:20\r\n<font color=\"#ff0000\">Herêh</font>\r\n";
+        assert_eq!(epa_subtitles(srt, &EpaOptions::default(), Subtitles::Srt).expect("Wrong parser"), expected);
+
+        let vtt = "WEBVTT - Hierro\nKind: captions\n\nNOTE el hierro\nde Zaragoza\n\n\
+                   hierro-1\n00:01.000 --> 00:03.000 align:start\n<v Juan Hierro>El hierro &amp; \
+                   <lang en>the hierro</lang> <c.yellow>zapato</c>\n";
+        let expected = "WEBVTT - Hierro\nKind: captions\n\nNOTE el hierro\nde Zaragoza\n\n\
+                        hierro-1\n00:01.000 --> 00:03.000 align:start\n<v Juan Hierro>El ierro &amp; \
+                        <lang en>the hierro</lang> <c.yellow>çapato</c>\n";
+        assert_eq!(epa_subtitles(vtt, &EpaOptions::default(), Subtitles::WebVtt).expect("Wrong parser"), expected);
+    }
+}
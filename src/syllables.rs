@@ -0,0 +1,269 @@
+// Syllables of spanish words and the one that carries the stress, that the
+// word ending rules and the IPA transcription need to know.
+
+// Where the stress falls, counting the syllables from the end of the word
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Stress {
+    // On the last syllable, like reloj
+    Aguda,
+    // On the one before, like lápiz
+    Llana,
+    // On the third to last, like esdrújula
+    Esdrujula,
+    // Any one before, like dígaselo
+    Sobresdrujula,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    // a, e, o and the weak ones with a tilde, that break diphthongs
+    Strong,
+    // i, u, ü and y at the end of a diphthong
+    Weak,
+    Consonant,
+}
+
+// A sound of the word, a letter or one of the digraphs that can't be split,
+// written in lowercase
+pub(crate) struct Unit {
+    pub(crate) kind: Kind,
+    pub(crate) text: String,
+}
+
+pub(crate) fn is_vowel(c: char) -> bool {
+    "aeiouáéíóúüâêîôû".contains(c)
+}
+
+pub(crate) fn vowel_kind(c: char) -> Kind {
+    match c {
+        'i' | 'u' | 'ü' | 'î' | 'û' | 'y' => Kind::Weak,
+        _ => Kind::Strong,
+    }
+}
+
+fn units(word: &str) -> Vec<Unit> {
+    let chars: Vec<char> = word.chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect();
+    let mut units = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        let after = chars.get(i + 2).cloned();
+
+        let (kind, len) = match (c, next) {
+            ('c', Some('h')) | ('l', Some('l')) | ('r', Some('r')) => (Kind::Consonant, 2),
+            // The u of que, qui, gue and gui isn't a vowel
+            ('q', Some('u')) | ('g', Some('u')) if after.is_some_and(|a| "eiéí".contains(a)) => {
+                (Kind::Consonant, 2)
+            },
+            // y is a vowel when no vowel follows it, like in rey or muy
+            ('y', n) if !n.is_some_and(is_vowel) && i > 0 => (Kind::Weak, 1),
+            (c, _) if is_vowel(c) => (vowel_kind(c), 1),
+            _ => (Kind::Consonant, 1),
+        };
+
+        let text = chars[i..i + len].iter().collect();
+        units.push(Unit { kind, text });
+        i += len;
+    }
+
+    units
+}
+
+// Whether two consonants start a syllable together, like pr or bl
+fn is_onset(first: &str, second: &str) -> bool {
+    matches!(first, "p" | "b" | "f" | "c" | "g" | "k" | "t" | "d")
+        && matches!(second, "l" | "r")
+        && !matches!((first, second), ("d", "l") | ("t", "l"))
+}
+
+// Whether a vowel goes in the same syllable as the vowels before it
+fn joins(nucleus: &[&Unit], unit: &Unit) -> bool {
+    let last = match nucleus.last() {
+        Some(last) => last,
+        None => return true,
+    };
+
+    match (last.kind, unit.kind) {
+        (Kind::Weak, Kind::Weak) => last.text != unit.text,
+        (_, Kind::Strong) => nucleus.iter().all(|u| u.kind != Kind::Strong),
+        _ => true,
+    }
+}
+
+// Indexes of the units that start every syllable, none when there's no vowel
+pub(crate) fn split(units: &[Unit]) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut nucleus: Vec<&Unit> = vec![];
+    let mut consonants: Vec<usize> = vec![];
+
+    for (i, unit) in units.iter().enumerate() {
+        if unit.kind == Kind::Consonant {
+            consonants.push(i);
+            continue;
+        }
+
+        match (nucleus.is_empty(), consonants.len()) {
+            // The first vowel of the word
+            (true, _) => {},
+            (false, 0) if joins(&nucleus, unit) => {},
+            (false, 0) => {
+                starts.push(i);
+                nucleus.clear();
+            },
+            (false, 1) => {
+                starts.push(consonants[0]);
+                nucleus.clear();
+            },
+            (false, n) => {
+                let first = &units[consonants[n - 2]].text;
+                let second = &units[consonants[n - 1]].text;
+                match is_onset(first, second) {
+                    true => starts.push(consonants[n - 2]),
+                    false => starts.push(consonants[n - 1]),
+                }
+                nucleus.clear();
+            },
+        }
+
+        nucleus.push(unit);
+        consonants.clear();
+    }
+
+    match nucleus.is_empty() {
+        true => vec![],
+        false => starts,
+    }
+}
+
+// Index of the syllable with the stress, the one with a tilde or the one the
+// ending of the word tells, for the units split at starts
+pub(crate) fn stressed(units: &[Unit], starts: &[usize]) -> Option<usize> {
+    if starts.is_empty() {
+        return None;
+    }
+
+    let tilde = units.iter().position(|u| u.text.contains(['á', 'é', 'í', 'ó', 'ú']))
+        .map(|i| starts.iter().rposition(|s| *s <= i).unwrap_or(0));
+    if tilde.is_some() {
+        return tilde;
+    }
+
+    let n = starts.len();
+    let letters: Vec<&Unit> = units.iter().filter(|u| u.text.chars().all(char::is_alphabetic)).collect();
+    // The y of rey counts as a consonant
+    let is_vowel = |u: &Unit| u.kind != Kind::Consonant && u.text != "y";
+    let llana = match letters[..] {
+        [.., last] if is_vowel(last) => true,
+        // Not like bíceps or robots
+        [.., v, last] if last.text == "n" || last.text == "s" => is_vowel(v),
+        _ => false,
+    };
+
+    match llana && n > 1 {
+        true => Some(n - 2),
+        false => Some(n - 1),
+    }
+}
+
+/// Stress of a word, or of the last one of a compound like político-social
+pub(crate) fn stress(word: &str) -> Option<Stress> {
+    let word = word.rsplit(|c: char| !c.is_alphabetic())
+        .find(|w| !w.is_empty())
+        .unwrap_or(word);
+    let units = units(word);
+    let starts = split(&units);
+    stressed(&units, &starts).map(|i| match starts.len() - 1 - i {
+        0 => Stress::Aguda,
+        1 => Stress::Llana,
+        2 => Stress::Esdrujula,
+        _ => Stress::Sobresdrujula,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syllables(word: &str) -> String {
+        let units = units(word);
+        let starts = split(&units);
+        units.iter().enumerate()
+            .map(|(i, u)| match i > 0 && starts.contains(&i) {
+                true => format!("-{}", u.text),
+                false => u.text.clone(),
+            }).collect()
+    }
+
+    #[test]
+    fn test_syllables() {
+        let words = vec![
+            ("reloj", "re-loj"),
+            ("lápiz", "lá-piz"),
+            ("caracteres", "ca-rac-te-res"),
+            ("Madrid", "ma-drid"),
+            ("césped", "cés-ped"),
+            ("bíceps", "bí-ceps"),
+            ("instrumento", "ins-tru-men-to"),
+            ("perspectiva", "pers-pec-ti-va"),
+            ("atleta", "at-le-ta"),
+            ("chorrillo", "cho-rri-llo"),
+            ("guerrero", "gue-rre-ro"),
+            ("pingüino", "pin-güi-no"),
+            ("quiosco", "quios-co"),
+            // Diphthongs, triphthongs and hiatus
+            ("cuidado", "cui-da-do"),
+            ("ciudad", "ciu-dad"),
+            ("Uruguay", "u-ru-guay"),
+            ("buey", "buey"),
+            ("ayuda", "a-yu-da"),
+            ("poeta", "po-e-ta"),
+            ("ataúd", "a-ta-úd"),
+            ("Raúl", "ra-úl"),
+            ("oído", "o-í-do"),
+            ("leía", "le-í-a"),
+            ("chiita", "chi-i-ta"),
+            ("paranoia", "pa-ra-noi-a"),
+            ("¡Hola!", "¡ho-la!"),
+            ("y", "y"),
+            ("3", "3"),
+        ];
+
+        for (word, expected) in words {
+            assert_eq!(syllables(word), expected);
+        }
+    }
+
+    #[test]
+    fn test_stress() {
+        let words = vec![
+            ("reloj", Some(Stress::Aguda)),
+            ("lápiz", Some(Stress::Llana)),
+            ("caracteres", Some(Stress::Llana)),
+            ("carácter", Some(Stress::Llana)),
+            ("Madrid", Some(Stress::Aguda)),
+            ("CÉSPED", Some(Stress::Llana)),
+            ("virtud", Some(Stress::Aguda)),
+            ("ataúd", Some(Stress::Aguda)),
+            ("Sáez", Some(Stress::Llana)),
+            ("bíceps", Some(Stress::Llana)),
+            ("robots", Some(Stress::Aguda)),
+            ("colocados", Some(Stress::Llana)),
+            ("examen", Some(Stress::Llana)),
+            ("Uruguay", Some(Stress::Aguda)),
+            ("válido", Some(Stress::Esdrujula)),
+            ("dígaselo", Some(Stress::Sobresdrujula)),
+            ("sed", Some(Stress::Aguda)),
+            ("político-social", Some(Stress::Aguda)),
+            ("¿Cádiz", Some(Stress::Llana)),
+            ("3", None),
+        ];
+
+        for (word, expected) in words {
+            assert_eq!(stress(word), expected, "{}", word);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::ops::Range;
+
+use crate::Rule;
+
+/// A piece of a step input rewritten by a rule
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    /// Grammar rule that fired, `None` for a word found in an exception table or
+    /// text rewritten without the grammar
+    pub rule: Option<Rule>,
+    /// Byte range in the step input
+    pub span: Range<usize>,
+    pub output: String,
+}
+
+/// What a rule of the pipeline did to the text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub name: String,
+    pub input: String,
+    pub output: String,
+    pub matches: Vec<Match>,
+}
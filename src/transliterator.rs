@@ -0,0 +1,331 @@
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::pipeline::Pipeline;
+use crate::{exceptions, explain, stream, Alignment, Dictionary, EpaOptions, Error, Exceptions, HtmlOptions, IpaOptions,
+            Normalization, PoOptions, Proposal, Resource, Step, Subtitles, Transformation, Vaf, Vvf, Warning};
+
+/// A pipeline set up once, to transliterate any number of texts from any thread
+///
+/// ```
+/// use andaluh::{Transliterator, Vaf};
+///
+/// let transliterator = Transliterator::builder()
+///     .vaf(Vaf::S)
+///     .build()
+///     .expect("Wrong configuration");
+/// assert_eq!(transliterator.transliterate("Zaragoza").unwrap(), "Saragosa");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Transliterator {
+    proposal: Proposal,
+    options: EpaOptions,
+    pipeline: Pipeline,
+}
+
+impl Transliterator {
+    pub fn builder() -> TransliteratorBuilder {
+        TransliteratorBuilder::default()
+    }
+
+    pub fn proposal(&self) -> Proposal {
+        self.proposal
+    }
+
+    pub fn options(&self) -> &EpaOptions {
+        &self.options
+    }
+
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// Names of the enabled rules, in the order they run
+    pub fn rules(&self) -> impl Iterator<Item = &str> {
+        self.pipeline.names()
+    }
+
+    pub fn transliterate(&self, input: &str) -> Result<String, Error> {
+        self.pipeline.transliterate(input, &self.options)
+    }
+
+    /// Transliterates the text of reader, writing it to writer as it goes
+    pub fn transliterate_stream<R: BufRead, W: Write>(&self, reader: R, writer: W) -> Result<(), Error> {
+        stream::transliterate(&self.pipeline, &self.options, reader, writer).map(|_| ())
+    }
+
+    /// Transliterates the text of reader in the lenient mode, returning the
+    /// text left untouched
+    pub fn transliterate_stream_lenient<R: BufRead, W: Write>(&self, reader: R, writer: W)
+        -> Result<Vec<Warning>, Error> {
+        let options = EpaOptions { lenient: true, ..self.options.clone() };
+        stream::transliterate(&self.pipeline, &options, reader, writer)
+    }
+
+    /// Transliterates in the lenient mode, returning the text left untouched
+    pub fn transliterate_lenient(&self, input: &str) -> Result<(String, Vec<Warning>), Error> {
+        self.pipeline.transliterate_lenient(input, &self.options)
+    }
+
+    /// Transliterates returning where every piece of the output comes from
+    pub fn transliterate_aligned(&self, input: &str) -> Result<(String, Vec<Alignment>), Error> {
+        self.pipeline.transliterate_aligned(input, &self.options)
+    }
+
+    /// Transliterates a single word, with the transformations every rule made
+    pub fn transliterate_word(&self, word: &str) -> Result<(String, Vec<Transformation>), Error> {
+        explain::transliterate_word(&self.pipeline, word, &self.options)
+    }
+
+    /// Transliterates the text of an HTML document, keeping its markup as it
+    /// is
+    pub fn transliterate_html(&self, input: &str, html_options: &HtmlOptions) -> Result<String, Error> {
+        self.pipeline.transliterate_html(input, &self.options, html_options)
+    }
+
+    /// Transliterates the prose of a Markdown document, keeping code, URLs,
+    /// HTML blocks and the front matter as they are
+    pub fn transliterate_markdown(&self, input: &str) -> Result<String, Error> {
+        self.pipeline.transliterate_markdown(input, &self.options)
+    }
+
+    /// Transliterates the text of the cues of SRT or WebVTT subtitles,
+    /// keeping their timings and styling tags as they are
+    pub fn transliterate_subtitles(&self, input: &str, format: Subtitles) -> Result<String, Error> {
+        self.pipeline.transliterate_subtitles(input, &self.options, format)
+    }
+
+    /// Transliterates the translations of a gettext PO catalogue, keeping the
+    /// placeholders, comments and flags as they are
+    pub fn transliterate_po(&self, input: &str, po_options: &PoOptions) -> Result<String, Error> {
+        self.pipeline.transliterate_po(input, &self.options, po_options)
+    }
+
+    /// Transliterates the string values of a JSON or YAML locale file,
+    /// keeping the keys, the ICU MessageFormat syntax and the placeholders as
+    /// they are
+    pub fn transliterate_resource(&self, input: &str, format: Resource) -> Result<String, Error> {
+        self.pipeline.transliterate_resource(input, &self.options, format)
+    }
+
+    /// Broad IPA transcription of the pronunciation the EPA spelling writes
+    pub fn transcribe_ipa(&self, input: &str, ipa_options: &IpaOptions) -> Result<String, Error> {
+        self.pipeline.transcribe_ipa(input, &self.options, ipa_options)
+    }
+
+    /// Transliterates keeping what every rule did to the text
+    pub fn trace(&self, input: &str) -> Result<Vec<Step>, Error> {
+        self.pipeline.trace(input, &self.options)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransliteratorBuilder {
+    proposal: Proposal,
+    options: EpaOptions,
+    // The one of the proposal when it's not set
+    pipeline: Option<Pipeline>,
+    // All of them when it's not set
+    rules: Option<Vec<String>>,
+    // Files of exceptions, loaded when building
+    exception_files: Vec<PathBuf>,
+}
+
+impl TransliteratorBuilder {
+    pub fn proposal(mut self, proposal: Proposal) -> Self {
+        self.proposal = proposal;
+        self
+    }
+
+    pub fn vaf(mut self, vaf: Vaf) -> Self {
+        self.options.vaf = vaf;
+        self
+    }
+
+    pub fn vvf(mut self, vvf: Vvf) -> Self {
+        self.options.vvf = vvf;
+        self
+    }
+
+    pub fn escape_links(mut self, escape: bool) -> Self {
+        self.options.escape_links = escape;
+        self
+    }
+
+    pub fn transliterate_hashtags(mut self, transliterate: bool) -> Self {
+        self.options.transliterate_hashtags = transliterate;
+        self
+    }
+
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.options.lenient = lenient;
+        self
+    }
+
+    /// Unicode form of the output
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.options.normalization = normalization;
+        self
+    }
+
+    /// Runs these rules instead of the ones of the proposal
+    pub fn pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    /// Only runs these rules of the pipeline, by name, in its order
+    pub fn rules<S: AsRef<str>>(mut self, rules: &[S]) -> Self {
+        self.rules = Some(rules.iter().map(|r| r.as_ref().to_string()).collect());
+        self
+    }
+
+    /// Adds a dictionary of exceptions, its words replace the ones already added
+    pub fn exceptions(mut self, dictionary: &Dictionary) -> Self {
+        self.options.exceptions.extend(dictionary);
+        self
+    }
+
+    /// Adds words to the exceptions of the rules, replacing the ones already
+    /// added
+    pub fn rule_exceptions(mut self, exceptions: &Exceptions) -> Self {
+        self.options.rule_exceptions.extend(exceptions);
+        self
+    }
+
+    /// Adds the exceptions of a file, loaded by `build` after the ones
+    /// already added
+    pub fn exceptions_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.exception_files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn build(mut self) -> Result<Transliterator, Error> {
+        for path in self.exception_files.iter() {
+            self.options.rule_exceptions.extend(&Exceptions::load(path)?);
+        }
+
+        if self.options.transliterate_hashtags && !self.options.escape_links {
+            return Err(Error::InvalidConfig(
+                "transliterate_hashtags needs escape_links".to_string()));
+        }
+
+        exceptions::check(&self.options)?;
+
+        let mut pipeline = match (self.pipeline, self.proposal) {
+            (Some(pipeline), _) => pipeline,
+            (None, Proposal::Epa) => Pipeline::epa(),
+        };
+
+        if let Some(names) = self.rules {
+            if let Some(name) = names.iter().find(|n| pipeline.names().all(|r| r != *n)) {
+                return Err(Error::InvalidConfig(format!("unknown rule {:?}", name)));
+            }
+
+            let disabled: Vec<String> = pipeline.names()
+                .filter(|r| names.iter().all(|n| n != r))
+                .map(String::from)
+                .collect();
+            for name in disabled {
+                pipeline.remove(&name)?;
+            }
+        }
+
+        Ok(Transliterator { proposal: self.proposal, options: self.options, pipeline })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::epa_with;
+
+    #[test]
+    fn test_transliterator() {
+        let input = "Gente y Cielo de Cádiz en https://www.sevilla.es/cielo";
+        let transliterator = Transliterator::builder()
+            .vaf(Vaf::Z)
+            .vvf(Vvf::J)
+            .escape_links(true)
+            .build()
+            .expect("Wrong configuration");
+
+        let output = transliterator.transliterate(input).expect("Wrong parser");
+        assert_eq!(output, epa_with(input, transliterator.options()).expect("Wrong parser"));
+        assert_eq!(output, "Jente y Zielo de Cádî en https://www.sevilla.es/cielo");
+
+        let transliterator = Transliterator::builder()
+            .rules(&["v_rule", "h_rule"])
+            .build()
+            .expect("Wrong configuration");
+        assert_eq!(transliterator.rules().collect::<Vec<_>>(), vec!["h_rule", "v_rule"]);
+        assert_eq!(transliterator.transliterate("Hola Valencia").expect("Wrong parser"), "Ola Balencia");
+    }
+
+    #[test]
+    fn test_transliterator_exceptions() {
+        let dictionary: Dictionary = vec![("sevilla", "Çebiya"), ("betis", "Bétî")].into_iter().collect();
+        let transliterator = Transliterator::builder()
+            .vaf(Vaf::S)
+            .exceptions(&dictionary)
+            .build()
+            .expect("Wrong configuration");
+
+        let output = transliterator.transliterate("Sevilla y BETIS, en Zaragoza").expect("Wrong parser");
+        assert_eq!(output, "Sebiya y BÉTÎ, en Saragosa");
+    }
+
+    #[test]
+    fn test_transliterator_rule_exceptions() {
+        let path = std::env::temp_dir().join(format!("andaluh-{}.tsv", std::process::id()));
+        std::fs::write(&path, "h_rule\thierro\tyerro\nexception_rule\ttodo\ttôh\n").unwrap();
+        let transliterator = Transliterator::builder()
+            .exceptions_file(&path)
+            .build();
+        std::fs::remove_file(&path).unwrap();
+
+        let transliterator = transliterator.expect("Wrong configuration");
+        assert_eq!(transliterator.transliterate("El Hierro todo").expect("Wrong parser"), "Er Yerro tôh");
+
+        match Transliterator::builder().exceptions_file(&path).build() {
+            Err(Error::Dictionary { path: p, line: 0, .. }) if p == path => {},
+            other => panic!("Wrong configuration {:?}", other.map(|t| t.options().clone())),
+        }
+    }
+
+    #[test]
+    fn test_transliterator_invalid_config() {
+        let dictionary: Dictionary = vec![("la giralda", "la hiralda")].into_iter().collect();
+        let mut rule_exceptions = Exceptions::new();
+        rule_exceptions.insert("h_rule", "hierro", "el yerro");
+        let builders = vec![
+            Transliterator::builder().rules(&["h_rule", "z_rule"]),
+            Transliterator::builder().rules(&["vaf_output"]),
+            Transliterator::builder().transliterate_hashtags(true),
+            Transliterator::builder().exceptions(&dictionary),
+            Transliterator::builder().exceptions(&vec![("alante", "a lante")].into_iter().collect()),
+            Transliterator::builder().rule_exceptions(&rule_exceptions),
+        ];
+
+        for builder in builders {
+            match builder.build() {
+                Err(Error::InvalidConfig(_)) => {},
+                other => panic!("Wrong configuration {:?}", other.map(|t| t.options().clone())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transliterator_send_sync() {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<Transliterator>();
+
+        let transliterator = Transliterator::builder().build().expect("Wrong configuration");
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| assert_eq!(transliterator.transliterate("Chungo").unwrap(), "Xungo"));
+            }
+        });
+    }
+}